@@ -1,37 +1,261 @@
-use super::task::*;
 use super::*;
+use std::fmt::{self, Debug, Display, Formatter};
 
 /// Run an iterator of tasks as a Selector
-pub fn selector<'a, T: 'a + TaskInput, It: Iterator<Item = &'a Task<'a, T>>>(
-    state: &'a mut T,
-    tasks: It,
-) -> ExecutionResult {
-    let found = tasks
-        .map(|node| (node, node.tick(state)))
-        .find(|(_node, result)| result.is_err());
-    if let Some(found) = found {
-        Err(format!("A task failed in Selector {:?}", found.1))?;
+/// Stops at the first task that fails or is running
+/// Continues from the task that was running in the previous tick
+/// Fails with the error of the failed task
+pub fn selector<'a, T, N, It>(state: &'a mut T, tasks: It) -> ExecutionResult
+where
+    T: 'a + TaskInput,
+    N: 'a + BtNode<T> + ?Sized,
+    It: Iterator<Item = &'a N>,
+{
+    let tasks = tasks.collect::<Vec<_>>();
+    let start = resume_index(state, tasks.len()).unwrap_or(0);
+    for (i, node) in tasks.iter().enumerate().skip(start) {
+        match tick_child(state, i, *node) {
+            Ok(Status::Success) => {}
+            Ok(Status::Running) => return Ok(Status::Running),
+            Err(e) => return Err(e),
+        }
     }
-    Ok(())
+    Ok(Status::Success)
 }
 
 /// Run an iterator of tasks as a Sequence
-pub fn sequence<'a, T: 'a + TaskInput, It: Iterator<Item = &'a Task<'a, T>>>(
-    state: &'a mut T,
-    mut tasks: It,
-) -> ExecutionResult {
-    let found = tasks.any(|node| {
-        let result = node.tick(state);
+/// Stops at the first task that succeeds or is running
+/// The task that was running in the previous tick is tried first
+/// Fails with the error of the last task if all of them failed
+pub fn sequence<'a, T, N, It>(state: &'a mut T, tasks: It) -> ExecutionResult
+where
+    T: 'a + TaskInput,
+    N: 'a + BtNode<T> + ?Sized,
+    It: Iterator<Item = &'a N>,
+{
+    let tasks = tasks.collect::<Vec<_>>();
+    let resume = resume_index(state, tasks.len());
+    let order = resume
+        .into_iter()
+        .chain((0..tasks.len()).filter(|i| Some(*i) != resume));
+    let mut error = None;
+    for i in order {
+        let node = tasks[i];
+        let result = tick_child(state, i, node);
         debug!(
             "Task result in sequence node: {:?} result: {:?}",
             node, result
         );
-        result.is_ok()
-    });
-    if found {
-        Ok(())
+        match result {
+            Ok(status) => return Ok(status),
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error.unwrap_or_else(|| "Sequence has no tasks".into()))
+}
+
+/// Number of children of a Parallel node that have to succeed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuccessPolicy {
+    All,
+    Any,
+    AtLeast(usize),
+}
+
+impl SuccessPolicy {
+    fn required(self, n_children: usize) -> usize {
+        match self {
+            SuccessPolicy::All => n_children,
+            SuccessPolicy::Any => 1.min(n_children),
+            SuccessPolicy::AtLeast(n) => n.min(n_children),
+        }
+    }
+}
+
+/// Run an iterator of tasks in Parallel
+/// Ticks every task, regardless of the results of the others
+/// Succeeds if enough tasks succeeded according to `policy`,
+/// is running if the running tasks may still satisfy it, fails otherwise
+pub fn parallel<'a, T, N, It>(state: &'a mut T, policy: SuccessPolicy, tasks: It) -> ExecutionResult
+where
+    T: 'a + TaskInput,
+    N: 'a + BtNode<T> + ?Sized,
+    It: Iterator<Item = &'a N>,
+{
+    let tasks = tasks.collect::<Vec<_>>();
+    let required = policy.required(tasks.len());
+    let mut succeeded = 0;
+    let mut running = 0;
+    let mut error = None;
+    for (i, node) in tasks.iter().enumerate() {
+        match tick_child(state, i, *node) {
+            Ok(Status::Success) => succeeded += 1,
+            Ok(Status::Running) => running += 1,
+            Err(e) => error = Some(e),
+        }
+    }
+    let result = if succeeded >= required {
+        Ok(Status::Success)
+    } else if succeeded + running >= required {
+        return Ok(Status::Running);
     } else {
-        Err("All tasks failed in Sequence!")?
+        Err(error.unwrap_or_else(|| "Not enough tasks succeeded in Parallel".into()))
+    };
+    // Running children of a finished node should not be resumed
+    if let Some(path) = state.tree_path() {
+        path.discard_running();
+    }
+    result
+}
+
+/// Index of the child to resume, if it's in range
+fn resume_index<T: TaskInput>(state: &mut T, n_children: usize) -> Option<usize> {
+    state
+        .tree_path()
+        .and_then(|path| path.resume_index())
+        .filter(|i| *i < n_children)
+}
+
+fn begin_trace<T: TaskInput, N: BtNode<T> + ?Sized>(
+    state: &mut T,
+    index: usize,
+    node: &N,
+) -> Option<TraceHandle> {
+    state.tracer()?;
+    let path = state
+        .tree_path()
+        .map(|path| path.current().to_vec())
+        .unwrap_or_else(|| vec![index as u8]);
+    let label = node.label();
+    state.tracer().map(|tracer| tracer.begin(&path, label))
+}
+
+/// Tick the `index`th child of a node and record its path if it's running
+fn tick_child<T: TaskInput, N: BtNode<T> + ?Sized>(
+    state: &mut T,
+    index: usize,
+    node: &N,
+) -> ExecutionResult {
+    if let Some(path) = state.tree_path() {
+        path.enter(index);
     }
+    let trace = begin_trace(state, index, node);
+    let result = node.tick(state);
+    if let (Some(trace), Some(tracer)) = (trace, state.tracer()) {
+        tracer.end(trace, &result);
+    }
+    if let Some(path) = state.tree_path() {
+        if let Ok(Status::Running) = result {
+            path.set_running();
+        }
+        path.leave();
+    }
+    result
+}
+
+/// Selector node owning its children
+/// See `selector`
+pub struct Selector<'a, T> {
+    pub name: String,
+    children: Vec<Box<dyn BtNode<T> + 'a>>,
+}
+
+/// Sequence node owning its children
+/// See `sequence`
+pub struct Sequence<'a, T> {
+    pub name: String,
+    children: Vec<Box<dyn BtNode<T> + 'a>>,
+}
+
+/// Parallel node owning its children
+/// See `parallel`
+pub struct Parallel<'a, T> {
+    pub name: String,
+    pub policy: SuccessPolicy,
+    children: Vec<Box<dyn BtNode<T> + 'a>>,
 }
 
+macro_rules! impl_control_node {
+    (
+        $node: ident,
+        |$this: ident, $state: ident| $run: expr,
+        $default_name: expr
+        $(, $field: ident: $value: expr)*
+    ) => {
+        impl<'a, T: TaskInput> $node<'a, T> {
+            pub fn new() -> Self {
+                Self {
+                    name: $default_name.to_owned(),
+                    children: vec![],
+                    $($field: $value,)*
+                }
+            }
+
+            pub fn with_name(mut self, name: &str) -> Self {
+                self.name.clear();
+                self.name.push_str(name);
+                self
+            }
+
+            pub fn with_child<N: BtNode<T> + 'a>(mut self, child: N) -> Self {
+                self.children.push(Box::new(child));
+                self
+            }
+        }
+
+        impl<'a, T: TaskInput> BtNode<T> for $node<'a, T> {
+            fn tick(&self, state: &mut T) -> ExecutionResult {
+                let $this = self;
+                let $state = state;
+                $run
+            }
+
+            fn label(&self) -> String {
+                format!("{} {}", stringify!($node), self.name)
+            }
+        }
+
+        impl<'a, T> Display for $node<'a, T> {
+            fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+                write!(f, "{} {} [", stringify!($node), self.name)?;
+                for (i, child) in self.children.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", child)?;
+                }
+                write!(f, "]")
+            }
+        }
+
+        impl<'a, T> Debug for $node<'a, T> {
+            fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+                write!(f, "{}", self)
+            }
+        }
+    };
+}
+
+impl_control_node!(
+    Selector,
+    |node, state| selector(state, node.children.iter()),
+    "UNNAMED_SELECTOR"
+);
+impl_control_node!(
+    Sequence,
+    |node, state| sequence(state, node.children.iter()),
+    "UNNAMED_SEQUENCE"
+);
+impl_control_node!(
+    Parallel,
+    |node, state| parallel(state, node.policy, node.children.iter()),
+    "UNNAMED_PARALLEL",
+    policy: SuccessPolicy::All
+);
+
+impl<'a, T: TaskInput> Parallel<'a, T> {
+    pub fn with_policy(mut self, policy: SuccessPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
@@ -0,0 +1,505 @@
+//! Decorator nodes
+//! Modify the execution or the result of a single child node
+//!
+//! Time based decorators (`Cooldown`, `OncePerTick`, `Timeout`) keep their timestamps
+//! in the input's `NodeTimers`, keyed by their path in the tree,
+//! so there should be at most one of each kind on a single node
+//!
+use super::*;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+
+/// Timestamps kept by the time based decorators
+#[derive(Debug, Default, Clone)]
+pub struct NodeTimers {
+    /// Game time of the current tick
+    pub time: u32,
+    /// Structure: decorator key -> game time
+    pub timers: BTreeMap<String, u32>,
+}
+
+impl NodeTimers {
+    pub fn new(time: u32, timers: BTreeMap<String, u32>) -> Self {
+        Self { time, timers }
+    }
+
+    /// Drop the timers that no longer affect their nodes, so they do not pile up in memory
+    /// Elapsed cooldowns, past ticks of `OncePerTick` and the deadlines of nodes that are not
+    /// on the `running` path are dropped
+    pub fn retain_live(&mut self, running: Option<&[u8]>) {
+        let time = self.time;
+        let timers = mem::take(&mut self.timers);
+        self.timers = timers
+            .into_iter()
+            .filter(|(key, value)| {
+                let mut parts = key.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some("cooldown"), _) => time < *value,
+                    (Some("once"), _) => time == *value,
+                    (Some("timeout"), Some(path)) => match (running, parse_path(path)) {
+                        (Some(running), Some(path)) => running.starts_with(&path),
+                        _ => false,
+                    },
+                    _ => false,
+                }
+            })
+            .collect();
+    }
+}
+
+/// Key of a time based decorator in `NodeTimers`
+fn timer_key<T: TaskInput>(state: &mut T, kind: &str) -> String {
+    let path = state
+        .tree_path()
+        .map(|path| path_to_string(path.current()))
+        .unwrap_or_default();
+    format!("{}:{}", kind, path)
+}
+
+/// Inverse of `path_to_string`
+fn parse_path(path: &str) -> Option<NodePath> {
+    if path.is_empty() {
+        return Some(vec![]);
+    }
+    path.split('.').map(|i| i.parse().ok()).collect()
+}
+
+fn discard_running<T: TaskInput>(state: &mut T) {
+    if let Some(path) = state.tree_path() {
+        path.discard_running();
+    }
+}
+
+/// Fails if the child succeeds and succeeds if the child fails
+#[derive(Debug, Clone)]
+pub struct Inverter<N> {
+    child: N,
+}
+
+impl<N> Inverter<N> {
+    pub fn new(child: N) -> Self {
+        Self { child }
+    }
+}
+
+impl<T: TaskInput, N: BtNode<T>> BtNode<T> for Inverter<N> {
+    fn tick(&self, state: &mut T) -> ExecutionResult {
+        match self.child.tick(state) {
+            Ok(Status::Success) => Err(format!("Inverted the success of {}", self.child))?,
+            Ok(Status::Running) => Ok(Status::Running),
+            Err(_) => Ok(Status::Success),
+        }
+    }
+
+    fn label(&self) -> String {
+        format!("Inverter({})", self.child.label())
+    }
+}
+
+impl<N: Display> Display for Inverter<N> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Inverter({})", self.child)
+    }
+}
+
+/// Succeeds even if the child fails
+#[derive(Debug, Clone)]
+pub struct Succeeder<N> {
+    child: N,
+}
+
+impl<N> Succeeder<N> {
+    pub fn new(child: N) -> Self {
+        Self { child }
+    }
+}
+
+impl<T: TaskInput, N: BtNode<T>> BtNode<T> for Succeeder<N> {
+    fn tick(&self, state: &mut T) -> ExecutionResult {
+        match self.child.tick(state) {
+            Ok(status) => Ok(status),
+            Err(e) => {
+                debug!("Succeeder ignoring failure of {}: {}", self.child, e);
+                Ok(Status::Success)
+            }
+        }
+    }
+
+    fn label(&self) -> String {
+        format!("Succeeder({})", self.child.label())
+    }
+}
+
+impl<N: Display> Display for Succeeder<N> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Succeeder({})", self.child)
+    }
+}
+
+/// Fails regardless of the result of the child
+/// Useful for side effects in a Sequence that should not stop it
+#[derive(Debug, Clone)]
+pub struct AlwaysFail<N> {
+    child: N,
+}
+
+impl<N> AlwaysFail<N> {
+    pub fn new(child: N) -> Self {
+        Self { child }
+    }
+}
+
+impl<T: TaskInput, N: BtNode<T>> BtNode<T> for AlwaysFail<N> {
+    fn tick(&self, state: &mut T) -> ExecutionResult {
+        let result = self.child.tick(state);
+        discard_running(state);
+        Err(format!("AlwaysFail {} result: {:?}", self.child, result))?
+    }
+
+    fn label(&self) -> String {
+        format!("AlwaysFail({})", self.child.label())
+    }
+}
+
+impl<N: Display> Display for AlwaysFail<N> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "AlwaysFail({})", self.child)
+    }
+}
+
+/// Tick the child up to `n` times in the same tick
+/// Stops at the first failure or if the child is running
+#[derive(Debug, Clone)]
+pub struct Repeat<N> {
+    child: N,
+    n: u8,
+}
+
+impl<N> Repeat<N> {
+    pub fn new(n: u8, child: N) -> Self {
+        Self { child, n }
+    }
+}
+
+impl<T: TaskInput, N: BtNode<T>> BtNode<T> for Repeat<N> {
+    fn tick(&self, state: &mut T) -> ExecutionResult {
+        for _ in 0..self.n {
+            if self.child.tick(state)? == Status::Running {
+                return Ok(Status::Running);
+            }
+        }
+        Ok(Status::Success)
+    }
+
+    fn label(&self) -> String {
+        format!("Repeat({}, {})", self.n, self.child.label())
+    }
+}
+
+impl<N: Display> Display for Repeat<N> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Repeat({}, {})", self.n, self.child)
+    }
+}
+
+/// After the child succeeds it will not be ticked for `ticks` ticks
+#[derive(Debug, Clone)]
+pub struct Cooldown<N> {
+    child: N,
+    ticks: u32,
+}
+
+impl<N> Cooldown<N> {
+    pub fn new(ticks: u32, child: N) -> Self {
+        Self { child, ticks }
+    }
+}
+
+impl<T: TaskInput, N: BtNode<T>> BtNode<T> for Cooldown<N> {
+    fn tick(&self, state: &mut T) -> ExecutionResult {
+        let key = timer_key(state, "cooldown");
+        let time = {
+            let timers = state.node_timers().ok_or("Cooldown requires node timers")?;
+            let time = timers.time;
+            if let Some(ready) = timers.timers.get(&key).cloned() {
+                if time < ready {
+                    Err(format!("{} is on cooldown until {}", self.child, ready))?;
+                }
+                timers.timers.remove(&key);
+            }
+            time
+        };
+        let result = self.child.tick(state);
+        if let Ok(Status::Success) = result {
+            if let Some(timers) = state.node_timers() {
+                timers.timers.insert(key, time + self.ticks);
+            }
+        }
+        result
+    }
+
+    fn label(&self) -> String {
+        format!("Cooldown({}, {})", self.ticks, self.child.label())
+    }
+}
+
+impl<N: Display> Display for Cooldown<N> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Cooldown({}, {})", self.ticks, self.child)
+    }
+}
+
+/// Tick the child at most once per game tick
+#[derive(Debug, Clone)]
+pub struct OncePerTick<N> {
+    child: N,
+}
+
+impl<N> OncePerTick<N> {
+    pub fn new(child: N) -> Self {
+        Self { child }
+    }
+}
+
+impl<T: TaskInput, N: BtNode<T>> BtNode<T> for OncePerTick<N> {
+    fn tick(&self, state: &mut T) -> ExecutionResult {
+        let key = timer_key(state, "once");
+        {
+            let timers = state
+                .node_timers()
+                .ok_or("OncePerTick requires node timers")?;
+            let time = timers.time;
+            if timers.timers.get(&key) == Some(&time) {
+                Err(format!("{} was already ticked in this tick", self.child))?;
+            }
+            timers.timers.insert(key, time);
+        }
+        self.child.tick(state)
+    }
+
+    fn label(&self) -> String {
+        format!("OncePerTick({})", self.child.label())
+    }
+}
+
+impl<N: Display> Display for OncePerTick<N> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "OncePerTick({})", self.child)
+    }
+}
+
+/// Fails the child if it is still running after `ticks` ticks
+/// The deadline starts over when the node is entered again after it stopped running
+#[derive(Debug, Clone)]
+pub struct Timeout<N> {
+    child: N,
+    ticks: u32,
+}
+
+impl<N> Timeout<N> {
+    pub fn new(ticks: u32, child: N) -> Self {
+        Self { child, ticks }
+    }
+}
+
+impl<T: TaskInput, N: BtNode<T>> BtNode<T> for Timeout<N> {
+    fn tick(&self, state: &mut T) -> ExecutionResult {
+        let key = timer_key(state, "timeout");
+        let resumed = state.tree_path().map(|path| path.was_running());
+        let time = {
+            let timers = state.node_timers().ok_or("Timeout requires node timers")?;
+            let time = timers.time;
+            if resumed == Some(false) {
+                // Left over from a previous run of the node
+                timers.timers.remove(&key);
+            }
+            if let Some(deadline) = timers.timers.get(&key).cloned() {
+                if deadline <= time {
+                    timers.timers.remove(&key);
+                    Err(format!("{} timed out", self.child))?;
+                }
+            }
+            time
+        };
+        let result = self.child.tick(state);
+        if let Some(timers) = state.node_timers() {
+            match result {
+                Ok(Status::Running) => {
+                    timers.timers.entry(key).or_insert(time + self.ticks);
+                }
+                _ => {
+                    timers.timers.remove(&key);
+                }
+            }
+        }
+        result
+    }
+
+    fn label(&self) -> String {
+        format!("Timeout({}, {})", self.ticks, self.child.label())
+    }
+}
+
+impl<N: Display> Display for Timeout<N> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Timeout({}, {})", self.ticks, self.child)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, Clone)]
+    struct TestState {
+        results: String,
+        path: TreePath,
+        timers: NodeTimers,
+    }
+
+    impl TaskInput for TestState {
+        fn cpu_bucket(&self) -> Option<i16> {
+            None
+        }
+
+        fn tree_path(&mut self) -> Option<&mut TreePath> {
+            Some(&mut self.path)
+        }
+
+        fn node_timers(&mut self) -> Option<&mut NodeTimers> {
+            Some(&mut self.timers)
+        }
+    }
+
+    fn push_a(state: &mut TestState) -> ExecutionResult {
+        state.results.push('a');
+        Ok(Status::Success)
+    }
+
+    fn push_b(state: &mut TestState) -> ExecutionResult {
+        state.results.push('b');
+        Ok(Status::Running)
+    }
+
+    #[test]
+    fn test_inverter_and_repeat() {
+        js! {}; // Enables error messages in tests
+
+        let mut state = TestState::default();
+
+        Inverter::new(Task::new(push_a))
+            .tick(&mut state)
+            .expect_err("Should have failed");
+        Repeat::new(3, Task::new(push_a))
+            .tick(&mut state)
+            .expect("Should have succeeded");
+        // Running stops the repetition
+        Repeat::new(3, Task::new(push_b))
+            .tick(&mut state)
+            .expect("Should have been running");
+
+        assert_eq!(state.results, "aaaab");
+    }
+
+    #[test]
+    fn test_cooldown() {
+        js! {}; // Enables error messages in tests
+
+        let node = Cooldown::new(2, Task::new(push_a));
+        let mut state = TestState::default();
+
+        for time in 0..5 {
+            state.timers.time = time;
+            node.tick(&mut state).unwrap_or(Status::Success);
+        }
+
+        // Ticked at 0, 2 and 4
+        assert_eq!(state.results, "aaa");
+    }
+
+    /// Resume the node running in the last tick, as `CreepState` does between ticks
+    fn next_tick(state: &mut TestState, time: u32) {
+        let running = state.path.running().map(|path| path.to_vec());
+        state.path = TreePath::resume_from(running.unwrap_or_default());
+        state.timers.time = time;
+    }
+
+    #[test]
+    fn test_timeout() {
+        js! {}; // Enables error messages in tests
+
+        let node = Sequence::new().with_child(Timeout::new(2, Task::new(push_b)));
+        let mut state = TestState::default();
+
+        next_tick(&mut state, 10);
+        assert_eq!(node.tick(&mut state).ok(), Some(Status::Running));
+        next_tick(&mut state, 11);
+        assert_eq!(node.tick(&mut state).ok(), Some(Status::Running));
+        next_tick(&mut state, 12);
+        node.tick(&mut state).expect_err("Should have timed out");
+
+        assert_eq!(state.results, "bb");
+        assert!(state.timers.timers.is_empty());
+    }
+
+    #[test]
+    fn test_stale_timeout_is_reset() {
+        js! {}; // Enables error messages in tests
+
+        let node = Sequence::new().with_child(Timeout::new(2, Task::new(push_b)));
+        let mut state = TestState::default();
+
+        next_tick(&mut state, 10);
+        assert_eq!(node.tick(&mut state).ok(), Some(Status::Running));
+        // Another branch of the tree ran in between, leaving the deadline behind
+        next_tick(&mut state, 11);
+        state.path = TreePath::default();
+        assert_eq!(state.timers.timers.get("timeout:0"), Some(&12));
+
+        next_tick(&mut state, 20);
+        assert_eq!(node.tick(&mut state).ok(), Some(Status::Running));
+        assert_eq!(state.timers.timers.get("timeout:0"), Some(&22));
+    }
+
+    #[test]
+    fn test_dead_timers_are_dropped() {
+        let mut timers = NodeTimers::new(10, BTreeMap::new());
+        for (key, value) in [
+            ("cooldown:0", 10),
+            ("cooldown:1", 11),
+            ("once:2", 9),
+            ("once:3", 10),
+            ("timeout:4.1", 12),
+            ("timeout:5", 12),
+        ]
+        .iter()
+        {
+            timers.timers.insert(key.to_string(), *value);
+        }
+
+        timers.retain_live(Some(&[4, 1, 2]));
+        assert_eq!(
+            timers.timers.keys().cloned().collect::<Vec<_>>(),
+            vec!["cooldown:1", "once:3", "timeout:4.1"]
+        );
+
+        timers.retain_live(None);
+        assert_eq!(timers.timers.len(), 2);
+    }
+
+    #[test]
+    fn test_once_per_tick() {
+        js! {}; // Enables error messages in tests
+
+        let node = OncePerTick::new(Task::new(push_a));
+        let mut state = TestState::default();
+
+        node.tick(&mut state).expect("Should have succeeded");
+        node.tick(&mut state).expect_err("Should have failed");
+        state.timers.time = 1;
+        node.tick(&mut state).expect("Should have succeeded");
+
+        assert_eq!(state.results, "aa");
+    }
+}
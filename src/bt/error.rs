@@ -0,0 +1,108 @@
+//! Failure state of the nodes
+//! Expected outcomes (e.g. a creep being full) are kept apart from actual failures
+//! so they can be counted and logged differently
+//!
+use arrayvec::ArrayString;
+use screeps::ReturnCode;
+use std::fmt::{self, Display, Formatter};
+
+pub type ErrorMessage = ArrayString<[u8; 128]>;
+
+#[derive(Debug, Clone)]
+pub enum ExecutionError {
+    /// The input can not take any more, e.g. its store is full
+    Full,
+    /// The input has nothing to give, e.g. its store is empty
+    Empty,
+    /// No suitable target was found
+    NoTarget,
+    /// The CPU bucket is below the requirement of the node
+    BucketTooLow(i16),
+    /// The input is not in the state the node expects, e.g. it is not loading
+    WrongState,
+
+    /// A game action returned an unexpected code
+    ReturnCode(ReturnCode),
+    /// Failed to convert a JS value
+    Conversion(ErrorMessage),
+    /// Any other failure
+    Message(ErrorMessage),
+}
+
+impl ExecutionError {
+    pub fn conversion<S: AsRef<str>>(message: S) -> Self {
+        ExecutionError::Conversion(to_message(message.as_ref()))
+    }
+
+    /// Expected outcomes are part of the normal control flow, the rest are failures
+    pub fn is_expected(&self) -> bool {
+        match self {
+            ExecutionError::Full
+            | ExecutionError::Empty
+            | ExecutionError::NoTarget
+            | ExecutionError::BucketTooLow(_)
+            | ExecutionError::WrongState => true,
+            _ => false,
+        }
+    }
+
+    /// Name of the variant, used to aggregate errors in the statistics
+    pub fn category(&self) -> &'static str {
+        match self {
+            ExecutionError::Full => "full",
+            ExecutionError::Empty => "empty",
+            ExecutionError::NoTarget => "no_target",
+            ExecutionError::BucketTooLow(_) => "bucket_too_low",
+            ExecutionError::WrongState => "wrong_state",
+            ExecutionError::ReturnCode(_) => "return_code",
+            ExecutionError::Conversion(_) => "conversion",
+            ExecutionError::Message(_) => "message",
+        }
+    }
+}
+
+/// Truncates `s` to the capacity of the message
+fn to_message(s: &str) -> ErrorMessage {
+    let mut result = ErrorMessage::new();
+    let mut end = s.len().min(result.capacity());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    result.push_str(&s[..end]);
+    result
+}
+
+impl From<ReturnCode> for ExecutionError {
+    fn from(code: ReturnCode) -> Self {
+        ExecutionError::ReturnCode(code)
+    }
+}
+
+impl<'a> From<&'a str> for ExecutionError {
+    fn from(s: &'a str) -> Self {
+        ExecutionError::Message(to_message(s))
+    }
+}
+
+impl From<String> for ExecutionError {
+    fn from(s: String) -> Self {
+        Self::from(s.as_str())
+    }
+}
+
+impl Display for ExecutionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::Full => write!(f, "full"),
+            ExecutionError::Empty => write!(f, "empty"),
+            ExecutionError::NoTarget => write!(f, "no target"),
+            ExecutionError::BucketTooLow(required) => {
+                write!(f, "Bucket requirement: {} not met", required)
+            }
+            ExecutionError::WrongState => write!(f, "wrong state"),
+            ExecutionError::ReturnCode(code) => write!(f, "Unexpected ReturnCode {:?}", code),
+            ExecutionError::Conversion(message) => write!(f, "Conversion failed: {}", message),
+            ExecutionError::Message(message) => write!(f, "{}", message),
+        }
+    }
+}
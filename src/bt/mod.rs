@@ -1,50 +1,64 @@
 //! Simple Behaviour Tree implementation
 //! See [Wiki](https://en.wikipedia.org/wiki/Behavior_tree_(artificial_intelligence,_robotics_and_control))
 //! Notes about the way Screeps works:
-//!     - There is no 'Running' state normally found in BT's
+//!     - A tick can not be suspended, 'Running' nodes are resumed in the next tick
+//!       if the input keeps track of the `TreePath` between ticks
 //!     - There is no explicit Task cancellation
 //!
+// The nodes are a toolkit, the trees do not use every one of them at all times
+#![allow(dead_code)]
+
 pub mod control;
+pub mod decorator;
+pub mod error;
+pub mod path;
 pub mod task;
+pub mod trace;
 pub use self::control::*;
+pub use self::decorator::*;
+pub use self::error::*;
+pub use self::path::*;
 pub use self::task::*;
-use arrayvec::ArrayString;
-use std::fmt::{self, Display, Formatter};
+pub use self::trace::*;
 
-#[derive(Default, Debug, Clone)]
-pub struct ExecutionError(ArrayString<[u8; 128]>);
+/// State of a node that did not fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The node finished its work
+    Success,
+    /// The node needs more ticks to finish its work
+    Running,
+}
 
 /// Result of a task
-pub type ExecutionResult = Result<(), ExecutionError>;
+/// `Err` is the Failure state
+pub type ExecutionResult = Result<Status, ExecutionError>;
 
 /// Input to a Task
 pub trait TaskInput {
     fn cpu_bucket(&self) -> Option<i16>;
-}
 
-impl TaskInput for () {
-    fn cpu_bucket(&self) -> Option<i16> {
+    /// Position of the executing node in the tree
+    /// Inputs returning `None` always run their trees from the start
+    fn tree_path(&mut self) -> Option<&mut TreePath> {
         None
     }
-}
 
-impl<'a> From<&'a str> for ExecutionError {
-    fn from(s: &'a str) -> Self {
-        let mut result = Self::default();
-        result.0.push_str(s);
-        result
+    /// Timestamps of the time based decorators
+    /// Time based decorators fail if the input returns `None`
+    fn node_timers(&mut self) -> Option<&mut NodeTimers> {
+        None
     }
-}
 
-impl From<String> for ExecutionError {
-    fn from(s: String) -> Self {
-        Self::from(s.as_str())
+    /// Records the ticked nodes if tracing is enabled for the input
+    fn tracer(&mut self) -> Option<&mut Tracer> {
+        None
     }
 }
 
-impl Display for ExecutionError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0.as_str())
+impl TaskInput for () {
+    fn cpu_bucket(&self) -> Option<i16> {
+        None
     }
 }
 
@@ -54,6 +68,31 @@ pub fn sorted_by_priority<'a, T: TaskInput>(nodes: &mut [Task<'a, T>]) {
 
 pub trait BtNode<T>: std::fmt::Debug + std::fmt::Display {
     fn tick(&self, state: &mut T) -> ExecutionResult;
+
+    /// Short description of the node, not including its children
+    fn label(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl<'a, T, N: BtNode<T> + ?Sized> BtNode<T> for &'a N {
+    fn tick(&self, state: &mut T) -> ExecutionResult {
+        (**self).tick(state)
+    }
+
+    fn label(&self) -> String {
+        (**self).label()
+    }
+}
+
+impl<T, N: BtNode<T> + ?Sized> BtNode<T> for Box<N> {
+    fn tick(&self, state: &mut T) -> ExecutionResult {
+        (**self).tick(state)
+    }
+
+    fn label(&self) -> String {
+        (**self).label()
+    }
 }
 
 #[cfg(test)]
@@ -63,12 +102,17 @@ mod tests {
     #[derive(Debug, Default, Clone)]
     struct TestState {
         results: String,
+        path: TreePath,
     }
 
     impl TaskInput for TestState {
         fn cpu_bucket(&self) -> Option<i16> {
             None
         }
+
+        fn tree_path(&mut self) -> Option<&mut TreePath> {
+            Some(&mut self.path)
+        }
     }
 
     #[test]
@@ -78,7 +122,7 @@ mod tests {
         let mut tasks = [
             Task::new(|state: &mut TestState| {
                 state.results.push('a');
-                Ok(())
+                Ok(Status::Success)
             })
             .with_priority(-1),
             Task::new(|state: &mut TestState| {
@@ -87,12 +131,12 @@ mod tests {
             }),
             Task::new(|state: &mut TestState| {
                 state.results.push('c');
-                Ok(())
+                Ok(Status::Success)
             })
             .with_priority(5),
             Task::new(|state: &mut TestState| {
                 state.results.push('d');
-                Ok(())
+                Ok(Status::Success)
             })
             .with_priority(1),
         ];
@@ -106,5 +150,149 @@ mod tests {
         // The order by priority is cdba, but stop the execution at 'b'
         assert_eq!(state.results, "cdb");
     }
-}
 
+    #[test]
+    fn test_running_node_is_resumed() {
+        js! {}; // Enables error messages in tests
+
+        let tasks = [
+            Task::new(|state: &mut TestState| {
+                state.results.push('a');
+                Err("Fail")?
+            }),
+            Task::new(|state: &mut TestState| {
+                let tasks = [
+                    Task::new(|state: &mut TestState| {
+                        state.results.push('b');
+                        Ok(Status::Success)
+                    }),
+                    Task::new(|state: &mut TestState| {
+                        state.results.push('c');
+                        Ok(Status::Running)
+                    }),
+                ];
+                selector(state, tasks.iter())
+            }),
+            Task::new(|state: &mut TestState| {
+                state.results.push('d');
+                Ok(Status::Success)
+            }),
+        ];
+
+        let mut state = TestState::default();
+        let result = sequence(&mut state, tasks.iter()).expect("Expected to run");
+
+        assert_eq!(result, Status::Running);
+        assert_eq!(state.results, "abc");
+        assert_eq!(state.path.running(), Some(&[1, 1][..]));
+
+        let mut state = TestState {
+            path: TreePath::resume_from(state.path.running().unwrap().iter().cloned()),
+            ..TestState::default()
+        };
+        sequence(&mut state, tasks.iter()).expect("Expected to run");
+
+        // Skip the failing first task and the finished 'b' task
+        assert_eq!(state.results, "c");
+    }
+
+    #[test]
+    fn test_nested_nodes() {
+        js! {}; // Enables error messages in tests
+
+        let tree = Sequence::new()
+            .with_name("root")
+            .with_child(
+                Selector::new()
+                    .with_name("first")
+                    .with_child(
+                        Task::new(|state: &mut TestState| {
+                            state.results.push('a');
+                            Ok(Status::Success)
+                        })
+                        .with_name("a"),
+                    )
+                    .with_child(
+                        Task::new(|state: &mut TestState| {
+                            state.results.push('b');
+                            Err("Stop here")?
+                        })
+                        .with_name("b"),
+                    ),
+            )
+            .with_child(
+                Task::new(|state: &mut TestState| {
+                    state.results.push('c');
+                    Ok(Status::Success)
+                })
+                .with_name("c"),
+            );
+
+        let mut state = TestState::default();
+        tree.tick(&mut state).expect("Expected to succeed");
+
+        assert_eq!(state.results, "abc");
+        assert_eq!(
+            tree.to_string(),
+            "Sequence root [Selector first [Task a, Task b], Task c]"
+        );
+    }
+
+    #[test]
+    fn test_errors_are_propagated() {
+        js! {}; // Enables error messages in tests
+
+        let tasks = [
+            Task::new(|_: &mut TestState| Err(ExecutionError::Full)),
+            Task::new(|_: &mut TestState| Err(ExecutionError::NoTarget)),
+        ];
+
+        let mut state = TestState::default();
+
+        let error = selector(&mut state, tasks.iter()).expect_err("Should have failed");
+        assert_eq!(error.category(), "full");
+
+        // Sequence fails with the error of its last task
+        let error = sequence(&mut state, tasks.iter()).expect_err("Should have failed");
+        assert_eq!(error.category(), "no_target");
+        assert!(error.is_expected());
+    }
+
+    #[test]
+    fn test_parallel_ticks_all_children() {
+        js! {}; // Enables error messages in tests
+
+        let node = |policy| {
+            Parallel::new()
+                .with_policy(policy)
+                .with_child(Task::new(|state: &mut TestState| {
+                    state.results.push('a');
+                    Err("Fail")?
+                }))
+                .with_child(Task::new(|state: &mut TestState| {
+                    state.results.push('b');
+                    Ok(Status::Running)
+                }))
+                .with_child(Task::new(|state: &mut TestState| {
+                    state.results.push('c');
+                    Ok(Status::Success)
+                }))
+        };
+
+        let mut state = TestState::default();
+
+        let result = node(SuccessPolicy::Any).tick(&mut state);
+        assert_eq!(result.ok(), Some(Status::Success));
+        assert_eq!(state.path.running(), None);
+
+        let result = node(SuccessPolicy::AtLeast(2)).tick(&mut state);
+        assert_eq!(result.ok(), Some(Status::Running));
+        assert_eq!(state.path.running(), Some(&[1][..]));
+
+        node(SuccessPolicy::All)
+            .tick(&mut state)
+            .expect_err("Should have failed");
+
+        assert_eq!(state.results, "abcabcabc");
+    }
+}
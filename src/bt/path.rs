@@ -0,0 +1,90 @@
+//! Bookkeeping of the position of the executing node in the tree
+//! Used to resume nodes that returned `Running` in the previous tick
+//!
+
+/// Indices of children from the root of the tree to a node
+pub type NodePath = Vec<u8>;
+
+/// Format a path as dot separated indices
+pub fn path_to_string(path: &[u8]) -> String {
+    path.iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TreePath {
+    /// Path of the node currently being ticked
+    current: NodePath,
+    /// Path of the node that was running in the previous tick
+    resume: NodePath,
+    /// Path of the node that is running in this tick
+    running: Option<NodePath>,
+}
+
+impl TreePath {
+    /// Resume the node at `path` on the next tick of the tree
+    pub fn resume_from<It: IntoIterator<Item = u8>>(path: It) -> Self {
+        Self {
+            resume: path.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Path of the node that returned `Running` in this tick, if any
+    pub fn running(&self) -> Option<&[u8]> {
+        self.running.as_deref()
+    }
+
+    /// Path of the node currently being ticked
+    pub fn current(&self) -> &[u8] {
+        self.current.as_slice()
+    }
+
+    /// The current node or one of its descendants was running in the previous tick
+    pub fn was_running(&self) -> bool {
+        !self.resume.is_empty() && self.resume.starts_with(&self.current)
+    }
+
+    /// Index of the child to resume in the node currently being ticked
+    pub fn resume_index(&self) -> Option<usize> {
+        let depth = self.current.len();
+        if self.resume.len() > depth && self.resume[..depth] == self.current[..] {
+            Some(self.resume[depth] as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn enter(&mut self, index: usize) {
+        debug_assert!(index <= std::u8::MAX as usize);
+        self.current.push(index as u8);
+    }
+
+    pub fn leave(&mut self) {
+        self.current.pop();
+    }
+
+    /// Record the current node as running
+    /// The deepest node is recorded first, so parents will not overwrite it
+    pub fn set_running(&mut self) {
+        if self.running.is_none() {
+            self.running = Some(self.current.clone());
+        }
+    }
+
+    /// Forget the running node if it is the current node or one of its descendants
+    /// Used by nodes that turn a `Running` child into a different result
+    pub fn discard_running(&mut self) {
+        let current = &self.current;
+        let is_descendant = self
+            .running
+            .as_ref()
+            .map(|running| running.starts_with(current))
+            .unwrap_or(false);
+        if is_descendant {
+            self.running = None;
+        }
+    }
+}
@@ -95,10 +95,7 @@ where
         {
             (self.task)(state)
         } else {
-            Err(format!(
-                "Bucket requirement: {} not met",
-                self.required_bucket
-            ))?
+            Err(ExecutionError::BucketTooLow(self.required_bucket))
         }
     }
 }
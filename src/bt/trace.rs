@@ -0,0 +1,136 @@
+//! Opt-in recording of node executions
+//! Inputs returning a `Tracer` record every node ticked by a Selector or Sequence
+//!
+use super::*;
+
+/// Record of a single tick of a node
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry {
+    /// Path of the node in the tree
+    pub path: String,
+    pub node: String,
+    pub result: String,
+    /// CPU used by the node, including its children
+    pub cpu: f32,
+}
+
+/// Handle of an entry that is still being recorded
+#[derive(Debug, Clone, Copy)]
+pub struct TraceHandle {
+    index: usize,
+    start: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    /// Returns the CPU used so far
+    clock: fn() -> f64,
+    entries: Vec<TraceEntry>,
+}
+
+impl Tracer {
+    pub fn new(clock: fn() -> f64) -> Self {
+        Self {
+            clock,
+            entries: vec![],
+        }
+    }
+
+    /// Start recording a node, entries are kept in the order nodes were entered
+    pub fn begin(&mut self, path: &[u8], node: String) -> TraceHandle {
+        let index = self.entries.len();
+        self.entries.push(TraceEntry {
+            path: path_to_string(path),
+            node,
+            result: String::new(),
+            cpu: 0.,
+        });
+        TraceHandle {
+            index,
+            start: (self.clock)(),
+        }
+    }
+
+    pub fn end(&mut self, handle: TraceHandle, result: &ExecutionResult) {
+        let cpu = (self.clock)() - handle.start;
+        let entry = &mut self.entries[handle.index];
+        entry.cpu = cpu as f32;
+        entry.result = match result {
+            Ok(status) => format!("{:?}", status),
+            Err(e) => format!("Failure: {}", e),
+        };
+    }
+
+    pub fn entries(&self) -> &[TraceEntry] {
+        self.entries.as_slice()
+    }
+
+    pub fn into_entries(self) -> Vec<TraceEntry> {
+        self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct TestState {
+        path: TreePath,
+        tracer: Tracer,
+    }
+
+    impl TaskInput for TestState {
+        fn cpu_bucket(&self) -> Option<i16> {
+            None
+        }
+
+        fn tree_path(&mut self) -> Option<&mut TreePath> {
+            Some(&mut self.path)
+        }
+
+        fn tracer(&mut self) -> Option<&mut Tracer> {
+            Some(&mut self.tracer)
+        }
+    }
+
+    fn clock() -> f64 {
+        0.
+    }
+
+    #[test]
+    fn test_nodes_are_traced_in_order() {
+        js! {}; // Enables error messages in tests
+
+        let tree = Sequence::new()
+            .with_name("root")
+            .with_child(Task::new(|_: &mut TestState| Err("Nope")?).with_name("a"))
+            .with_child(
+                Selector::new()
+                    .with_name("inner")
+                    .with_child(Task::new(|_: &mut TestState| Ok(Status::Running)).with_name("b")),
+            );
+
+        let mut state = TestState {
+            path: TreePath::default(),
+            tracer: Tracer::new(clock),
+        };
+        tree.tick(&mut state).expect("Expected to run");
+
+        let entries = state
+            .tracer
+            .entries()
+            .iter()
+            .map(|e| format!("{} {} {}", e.path, e.node, e.result))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            entries,
+            [
+                "0 Task a Failure: Nope",
+                "1 Selector inner Running",
+                "1.0 Task b Running"
+            ]
+        );
+    }
+}
@@ -313,7 +313,7 @@ mod tests {
         js! {}; // Enables error messages in tests
 
         let queue: ArrayQueue<[usize; SIZE]> =
-            [1, 2, 3, 4, 5, 6, 7, 8, 9].into_iter().map(|x| *x).into();
+            [1, 2, 3, 4, 5, 6, 7, 8, 9].iter().map(|x| *x).into();
 
         assert_eq!(queue.len(), 9);
 
@@ -332,7 +332,7 @@ mod tests {
 
         assert_eq!(q.len(), 9);
 
-        for (x, y) in [6, 7, 8, 9, 1, 2, 3, 4, 5].into_iter().zip(q.iter()) {
+        for (x, y) in [6, 7, 8, 9, 1, 2, 3, 4, 5].iter().zip(q.iter()) {
             assert_eq!(x, y);
         }
     }
@@ -361,7 +361,7 @@ mod tests {
         js! {};
 
         let mut queue: ArrayQueue<[i32; SIZE]> =
-            [1, 2, 3, 4, 5, 6, 7, 8, 9].into_iter().map(|x| *x).into();
+            [1, 2, 3, 4, 5, 6, 7, 8, 9].iter().map(|x| *x).into();
 
         assert_eq!(queue.len(), 9);
 
@@ -383,7 +383,7 @@ mod tests {
         js! {};
 
         let mut queue: ArrayQueue<[i32; SIZE]> =
-            [1, 2, 3, 4, 5, 6, 7, 8, 9].into_iter().map(|x| *x).into();
+            [1, 2, 3, 4, 5, 6, 7, 8, 9].iter().map(|x| *x).into();
 
         assert_eq!(queue.len(), 9);
 
@@ -4,7 +4,6 @@ mod trait_impl;
 
 pub use self::arrayqueue::*;
 pub use self::flaggrid::*;
-pub use self::trait_impl::*;
 
 use std::fmt;
 use std::ops::{Add, Sub};
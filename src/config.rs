@@ -0,0 +1,134 @@
+//! Runtime configuration, read from `Memory.xenos_config` every `CONFIG_RELOAD_INTERVAL` ticks
+//! Missing keys take their default value, the defaults are written if there is no config
+//!
+use log::LevelFilter;
+use serde_json;
+use std::str::FromStr;
+use stdweb::unstable::TryFrom;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Owner of the controllers that are not signed again
+    pub username: String,
+    /// Save statistics to the statistics segment
+    pub collect_stats: bool,
+    /// Trace the behaviour trees of one creep per role, one tower and one room of spawns
+    /// every N ticks, 0 disables tracing
+    pub trace_interval: u32,
+    /// One of `off`, `error`, `warn`, `info`, `debug` or `trace`
+    pub log_level: String,
+    /// Skip the tick if the CPU bucket is below this
+    pub min_bucket: i32,
+    /// Run the spawns every N ticks
+    pub spawn_skip: u32,
+    /// Number of upgraders per room with containers
+    pub upgrader_count: i8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            username: "Frenetiq".to_owned(),
+            collect_stats: false,
+            trace_interval: 0,
+            log_level: "info".to_owned(),
+            min_bucket: 500,
+            spawn_skip: 5,
+            upgrader_count: 1,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_json(raw: &str) -> Result<Self, String> {
+        let config: Self = serde_json::from_str(raw).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.username.is_empty() {
+            Err("username is empty")?;
+        }
+        LevelFilter::from_str(self.log_level.as_str())
+            .map_err(|_| format!("invalid log_level {:?}", self.log_level))?;
+        if self.min_bucket < 0 || self.min_bucket > 10_000 {
+            Err(format!(
+                "min_bucket {} is not in 0..=10000",
+                self.min_bucket
+            ))?;
+        }
+        if self.spawn_skip == 0 {
+            Err("spawn_skip has to be at least 1")?;
+        }
+        if self.upgrader_count < 0 {
+            Err(format!(
+                "upgrader_count {} is negative",
+                self.upgrader_count
+            ))?;
+        }
+        Ok(())
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        LevelFilter::from_str(self.log_level.as_str()).unwrap_or(LevelFilter::Info)
+    }
+
+    /// Read the config from Memory
+    /// Keeps the current config if the new one is invalid
+    pub fn reload(&mut self) {
+        let raw = js! {
+            const config = Memory.xenos_config;
+            return config === undefined ? null : JSON.stringify(config);
+        };
+        let raw = Option::<String>::try_from(raw).unwrap_or_else(|e| {
+            error!("Failed to read the config {:?}", e);
+            None
+        });
+        let config = match raw {
+            Some(raw) => Config::from_json(raw.as_str()),
+            None => {
+                info!("No config found, writing the defaults");
+                let config = Config::default();
+                let raw = serde_json::to_string(&config).expect("Config serializes");
+                js! {
+                    Memory.xenos_config = JSON.parse(@{raw});
+                }
+                Ok(config)
+            }
+        };
+        match config {
+            Ok(config) => {
+                if *self != config {
+                    info!("Config reloaded {:?}", config);
+                    *self = config;
+                }
+            }
+            // Not an error, errors are sent as notifications every tick
+            Err(e) => warn!("Invalid config, keeping the current one: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_keys_are_defaulted() {
+        let config = Config::from_json(r#"{"spawn_skip":3,"log_level":"debug"}"#).unwrap();
+        assert_eq!(config.spawn_skip, 3);
+        assert_eq!(config.log_level(), LevelFilter::Debug);
+        assert_eq!(config.username, Config::default().username);
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected() {
+        assert!(Config::default().validate().is_ok());
+        assert!(Config::from_json(r#"{"spawn_skip":0}"#).is_err());
+        assert!(Config::from_json(r#"{"log_level":"loud"}"#).is_err());
+        assert!(Config::from_json(r#"{"min_bucket":-1}"#).is_err());
+        assert!(Config::from_json(r#"{"upgrader_count":"one"}"#).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+//! Commands of the in-game console, exported as the global `xenos` object
+//! e.g. `xenos.expand("W1N1")`
+//! Commands run between ticks and return the text printed in the console
+//!
+use crate::creeps::Role;
+use crate::game_loop::with_game_state;
+use crate::logging;
+use crate::prelude::*;
+use log::LevelFilter;
+use std::str::FromStr;
+
+/// Define the global `xenos` object
+/// Called after every restart of the script
+pub fn register() {
+    js! {
+        const expand = @{expand};
+        const cancel_expansion = @{cancel_expansion};
+        const set_log_level = @{set_log_level};
+        const show_intel = @{show_intel};
+        const creep_info = @{creep_info};
+        const respawn_role = @{respawn_role};
+        const reload_config = @{reload_config};
+
+        global.xenos = {
+            expand: (room) => expand(String(room)),
+            cancelExpansion: (room) => cancel_expansion(String(room)),
+            setLogLevel: (level) => set_log_level(String(level)),
+            showIntel: (room) => show_intel(room === undefined ? null : String(room)),
+            creepInfo: (name) => creep_info(String(name)),
+            respawnRole: (room, role) => respawn_role(String(room), String(role)),
+            reloadConfig: () => reload_config(),
+        };
+    }
+}
+
+fn reply(result: Result<String, String>) -> String {
+    result.unwrap_or_else(|e| format!("Error: {}", e))
+}
+
+fn parse_room(room: &str) -> Result<WorldPosition, String> {
+    WorldPosition::parse_name(room).map_err(|e| format!("{} {:?}", e, room))
+}
+
+fn expand(room: String) -> String {
+    reply(parse_room(room.as_str()).and_then(|pos| {
+        with_game_state(|state| {
+            if state.expansion.insert(pos) {
+                format!("Expanding to {}", pos.to_string())
+            } else {
+                format!("Already expanding to {}", pos.to_string())
+            }
+        })
+    }))
+}
+
+fn cancel_expansion(room: String) -> String {
+    reply(parse_room(room.as_str()).and_then(|pos| {
+        with_game_state(|state| {
+            if state.expansion.remove(&pos) {
+                format!("Cancelled the expansion to {}", pos.to_string())
+            } else {
+                format!("Not expanding to {}", pos.to_string())
+            }
+        })
+    }))
+}
+
+/// Apply the changes of `Memory.xenos_config` without waiting for the next reload
+fn reload_config() -> String {
+    reply(with_game_state(|state| {
+        state.config.reload();
+        state.config_time = Some(screeps::game::time());
+        format!("{:#?}", state.config)
+    }))
+}
+
+/// Also written to the config, so it's kept after the next reload
+fn set_log_level(level: String) -> String {
+    let result = LevelFilter::from_str(level.as_str())
+        .map_err(|_| format!("Invalid log level {:?}", level))
+        .map(|filter| {
+            let level = level.to_lowercase();
+            js! {
+                const config = Memory.xenos_config || {};
+                config.log_level = @{level.as_str()};
+                Memory.xenos_config = config;
+            }
+            logging::set_level(filter);
+            format!("Log level set to {}", level)
+        });
+    reply(result)
+}
+
+/// Show the intel of a room or a summary of every room if no room is given
+fn show_intel(room: Option<String>) -> String {
+    let room = match room {
+        Some(room) => match parse_room(room.as_str()) {
+            Ok(pos) => Some(pos),
+            Err(e) => return reply(Err(e)),
+        },
+        None => None,
+    };
+    reply(with_game_state(|state| match room {
+        Some(pos) => state
+            .scout_intel
+            .get(&pos)
+            .map(|info| format!("{}: {:#?}", pos.to_string(), info))
+            .unwrap_or_else(|| format!("No intel on {}", pos.to_string())),
+        None => {
+            let mut result = format!("Intel on {} rooms", state.scout_intel.len());
+            for (pos, info) in state.scout_intel.iter() {
+                result.push_str(
+                    format!(
+                        "\n{}: {:?}, {} sources, recorded at {}",
+                        pos.to_string(),
+                        info.iff,
+                        info.n_sources,
+                        info.time_of_recording
+                    )
+                    .as_str(),
+                );
+            }
+            result
+        }
+    }))
+}
+
+fn creep_info(name: String) -> String {
+    reply(
+        with_game_state(|state| {
+            state
+                .creep_memory_get(CreepName(name.as_str()))
+                .map(|memory| format!("{}: {:#?}", name, memory))
+                .ok_or_else(|| format!("No memory of creep {:?}", name))
+        })
+        .and_then(|result| result),
+    )
+}
+
+/// Above every role but the defenders
+const CONSOLE_PRIORITY: i8 = 4;
+
+/// Spawn a creep of the role in the room before the ones the room is missing
+fn respawn_role(room: String, role: String) -> String {
+    let request =
+        parse_room(room.as_str()).and_then(|pos| Ok((pos, Role::from_str(role.as_str())?)));
+    reply(request.and_then(|(pos, role)| {
+        with_game_state(|state| {
+            let queue = state.spawn_queue(pos);
+            queue.push(SpawnRequest::new(role, CONSOLE_PRIORITY, "console"));
+            format!(
+                "Requested a {} in {}, {} requests in the room",
+                role,
+                pos.to_string(),
+                queue.len()
+            )
+        })
+    }))
+}
@@ -19,6 +19,8 @@ pub struct ConstructionMatrix {
     open_positions: ArrayQueue<[Point; 8]>,
 }
 
+/// Only read through `Debug` when logged
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum ConstructionMatrixError {
     OutOfSpace(String),
@@ -50,5 +50,5 @@ pub fn build_containers<'a>(room: &'a Room) -> ExecutionResult {
         });
     });
 
-    Ok(())
+    Ok(Status::Success)
 }
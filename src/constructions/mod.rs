@@ -8,60 +8,79 @@ mod storage;
 
 use self::construction_matrix::ConstructionMatrix;
 use self::point::Point;
+use crate::flags::{self, FlagCommand};
 use crate::prelude::*;
 use crate::state::ConstructionState;
-use crate::state::MemorySentinel;
+use crate::state::SegmentCache;
 use crate::CONSTRUCTIONS_SEGMENT;
 use screeps::{
     constants::{find, StructureType},
     objects::{HasPosition, Room, RoomPosition},
     ReturnCode,
 };
-use stdweb::unstable::{TryFrom, TryInto};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use stdweb::unstable::TryFrom;
+
+thread_local! {
+    static CONSTRUCTION_STATE: RefCell<SegmentCache<ConstructionState>> =
+        RefCell::new(SegmentCache::new(CONSTRUCTIONS_SEGMENT, 1));
+}
 
 pub fn run<'a>(state: &mut GameState) -> ExecutionResult {
     Task::new(_run).with_required_bucket(5000).tick(state)
 }
 
-fn _run(_state: &mut GameState) -> ExecutionResult {
+fn _run(game_state: &mut GameState) -> ExecutionResult {
     let time = screeps::game::time();
     let rooms = screeps::game::rooms::values();
     let len = rooms.len() as u32;
 
     if time % (len * 3) > len {
-        Err("Skipping constructions task")?;
+        Err(ExecutionError::WrongState)?;
     }
 
-    let mut state = ConstructionState::read_from_segment_or_default(CONSTRUCTIONS_SEGMENT);
-    let _sentinel = MemorySentinel::new(CONSTRUCTIONS_SEGMENT as u8, &state);
-
     let index = time % len;
     let room = &rooms[index as usize];
+    let room_state = game_state.room_state(room).clone();
 
-    manage_room(room, &mut state)
+    CONSTRUCTION_STATE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let mut state = cache.load(time).ok_or_else(|| {
+            warn!("Constructions segment is not loaded yet");
+            ExecutionError::WrongState
+        })?;
+        manage_room(room, &room_state, &mut state)
+    })
 }
 
-fn manage_room<'a>(room: &'a Room, state: &mut ConstructionState) -> ExecutionResult {
+fn manage_room<'a>(
+    room: &'a Room,
+    room_state: &RoomState,
+    state: &mut ConstructionState,
+) -> ExecutionResult {
     info!("Manage constructionSites of room {:?}", room.name());
 
-    let my = js! {
-        const room = @{room};
-        return room.controller && room.controller.my || false;
-    };
-    let my: bool = my.try_into().map_err(|e| {
-        error!("Failed to convert bool, {:?}", e);
-        "Conversion error"
-    })?;
-    if !my {
-        Err("Room is not mine")?;
+    if !room_state.my {
+        Err(ExecutionError::WrongState)?;
     }
 
-    build_structures(room, state).unwrap_or_else(|e| warn!("Failed build_structures {:?}", e));
-    containers::build_containers(room).unwrap_or_else(|e| warn!("Failed containers {:?}", e));
-    roads::build_roads(room, state).unwrap_or_else(|e| warn!("Failed roads {:?}", e));
-    build_storage(room, state).unwrap_or_else(|e| warn!("Failed storage {:?}", e));
+    build_structures(room, state)
+        .map(|_| ())
+        .unwrap_or_else(|e| warn!("Failed build_structures {:?}", e));
+    containers::build_containers(room)
+        .map(|_| ())
+        .unwrap_or_else(|e| warn!("Failed containers {:?}", e));
+    roads::build_roads(room, state)
+        .map(|_| ())
+        .unwrap_or_else(|e| warn!("Failed roads {:?}", e));
+    if room_state.level >= 4 && !room_state.has_storage {
+        build_storage(room, state)
+            .map(|_| ())
+            .unwrap_or_else(|e| warn!("Failed storage {:?}", e));
+    }
 
-    Ok(())
+    Ok(Status::Success)
 }
 
 fn build_storage(room: &Room, _state: &mut ConstructionState) -> ExecutionResult {
@@ -72,14 +91,14 @@ fn build_storage(room: &Room, _state: &mut ConstructionState) -> ExecutionResult
     let pos = pos.into_room_pos(&room.name());
     let result = room.create_construction_site(&pos, StructureType::Storage);
     match result {
-        ReturnCode::Ok => Ok(()),
+        ReturnCode::Ok => Ok(Status::Success),
         ReturnCode::Full => {
             debug!("Can't place construction site {:?}", result);
-            Err("Room is full")?
+            Err(ExecutionError::Full)?
         }
         _ => {
             debug!("Can't place construction site {:?}", result);
-            Err(format!("Failed to place construction site {:?}", result))?
+            Err(result)?
         }
     }
 }
@@ -92,6 +111,8 @@ fn build_structures<'a>(room: &'a Room, state: &'a mut ConstructionState) -> Exe
         StructureType::Spawn,
     ];
 
+    let blocked = blocked_points(room);
+
     let matrix = get_matrix_mut(state, room);
     let mut pos = matrix
         .find_next_pos(room)
@@ -106,6 +127,11 @@ fn build_structures<'a>(room: &'a Room, state: &'a mut ConstructionState) -> Exe
             error!("{}", err);
             err
         })?;
+        // The tile is kept until the flag is removed
+        if blocked.contains(&pos) {
+            debug!("Position {:?} is blocked by a flag", pos);
+            break;
+        }
         let result = room.create_construction_site(&roompos, *structure);
         match result {
             ReturnCode::InvalidTarget | ReturnCode::Ok => {
@@ -118,14 +144,22 @@ fn build_structures<'a>(room: &'a Room, state: &'a mut ConstructionState) -> Exe
             }
             ReturnCode::Full => {
                 debug!("Can' t place construction site {:?}", result);
-                Err("Room is full")?;
+                Err(ExecutionError::Full)?;
             }
             _ => {
                 debug!("Can't place construction site {:?}", result);
             }
         }
     }
-    Ok(())
+    Ok(Status::Success)
+}
+
+/// Tiles the room planner keeps off, see `FlagCommand::Block`
+fn blocked_points(room: &Room) -> BTreeSet<Point> {
+    flags::find_in_room(room, FlagCommand::Block)
+        .into_iter()
+        .map(|flag| Point::from(flag.pos()))
+        .collect()
 }
 
 fn get_matrix_mut<'a>(state: &'a mut ConstructionState, room: &Room) -> &'a mut ConstructionMatrix {
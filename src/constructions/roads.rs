@@ -39,6 +39,7 @@ pub fn build_roads<'a>(room: &'a Room, state: &'a mut ConstructionState) -> Exec
         Err(format!("Nothing to connect in room {}", room.name()))?;
     }
 
+    let blocked = blocked_points(room);
     let mut targets = targets.into_iter();
     let center = targets
         .next()
@@ -52,12 +53,12 @@ pub fn build_roads<'a>(room: &'a Room, state: &'a mut ConstructionState) -> Exec
         .filter(|pos| matrix.get(pos.x() as usize, pos.y() as usize) & CONNECTED_FLAG == 0)
         .collect();
     for pos in targets.iter() {
-        connect(&center, &pos, room).and_then(|_| {
+        connect(&center, &pos, room, &blocked).and_then(|_| {
             matrix.set_or(pos.x() as usize, pos.y() as usize, CONNECTED_FLAG);
-            Ok(())
+            Ok(Status::Success)
         })?;
     }
-    Ok(())
+    Ok(Status::Success)
 }
 
 fn can_continue_building(room: &Room) -> ExecutionResult {
@@ -86,10 +87,16 @@ fn can_continue_building(room: &Room) -> ExecutionResult {
         Err(format!("Room {} does not have a Tower yet", room.name()))?;
     }
 
-    Ok(())
+    Ok(Status::Success)
 }
 
-fn connect(pos0: &RoomPosition, pos1: &RoomPosition, room: &Room) -> ExecutionResult {
+/// Roads are not placed on the tiles blocked by a flag
+fn connect(
+    pos0: &RoomPosition,
+    pos1: &RoomPosition,
+    room: &Room,
+    blocked: &BTreeSet<Point>,
+) -> ExecutionResult {
     trace!(
         "Connecting {} {} and {} {} in room {}",
         pos0.x(),
@@ -114,16 +121,20 @@ fn connect(pos0: &RoomPosition, pos1: &RoomPosition, room: &Room) -> ExecutionRe
 
     if path.len() < 2 {
         trace!("points are too close to connect");
-        return Ok(());
+        return Ok(Status::Success);
     }
 
-    path[0..path.len() - 1].into_iter().try_for_each(|pos| {
-        let result = room.create_construction_site(pos, StructureType::Road);
-        if result == ReturnCode::Full {
-            Err("can't place any more construction sites".into())
-        } else {
-            Ok(())
-        }
-    })
+    path[0..path.len() - 1]
+        .iter()
+        .filter(|pos| !blocked.contains(&Point::from((*pos).clone())))
+        .try_for_each(|pos| {
+            let result = room.create_construction_site(pos, StructureType::Road);
+            if result == ReturnCode::Full {
+                Err(ExecutionError::Full)
+            } else {
+                Ok(())
+            }
+        })
+        .map(|_| Status::Success)
 }
 
@@ -1,6 +1,7 @@
 use super::geometry::PointGeometry;
 use super::ConstructionMatrix;
 use super::Point;
+use crate::flags::{self, FlagCommand};
 use screeps::{
     constants::find,
     objects::{HasPosition, Room},
@@ -16,6 +17,10 @@ pub fn find_storage_pos(room: &Room ) -> Result<Point, String> {
         Err("Room already has a storage")?;
     }
 
+    if let Some(flag) = flags::find_in_room(room, FlagCommand::Storage).first() {
+        return Ok(Point::from(flag.pos()));
+    }
+
     let poi = room
         .find(find::SOURCES)
         .into_iter()
@@ -1,15 +1,13 @@
 //! Takes Rooms
 //!
 use super::{
-    move_to, move_to_options, sign_controller_stock_msgs, update_scout_info, CreepState,
-    MoveToOptions,
+    move_to, move_to_range, sign_controller_stock_msgs, update_scout_info, CreepState, ROOM_RANGE,
+    SCOUT_INFO_INTERVAL,
 };
 use crate::prelude::*;
 use screeps::{prelude::*, ReturnCode};
 use stdweb::unstable::TryInto;
 
-const CONQUEST_TARGET: &'static str = "conquest_target";
-
 pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
     Task::new(_run)
         .with_required_bucket(300)
@@ -18,31 +16,29 @@ pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
 }
 
 fn _run(state: &mut CreepState) -> ExecutionResult {
-    let tasks = [
-        Task::new(|state: &mut CreepState| {
-            update_scout_info(state).unwrap_or_else(|e| {
-                error!("Failed to update scout info {:?}", e);
-            });
-            Err("continue")?
-        })
-        .with_name("Update scout info"),
-        Task::new(|state| claim_target(state)).with_name("Claim target"),
-        Task::new(|state| set_target(state)).with_name("Set target"),
-        Task::new(|state: &mut CreepState| sign_controller_stock_msgs(state.creep()))
-            .with_name("Set target"),
-    ];
-
-    sequence(state, tasks.iter())
+    Parallel::new()
+        .with_name("Conqueror")
+        .with_child(Succeeder::new(Cooldown::new(
+            SCOUT_INFO_INTERVAL,
+            Task::new(|state| update_scout_info(state)).with_name("Update scout info"),
+        )))
+        .with_child(
+            Sequence::new()
+                .with_name("Conquer")
+                .with_child(Task::new(|state| claim_target(state)).with_name("Claim target"))
+                .with_child(Task::new(|state| set_target(state)).with_name("Set target"))
+                .with_child(
+                    Task::new(|state: &mut CreepState| sign_controller_stock_msgs(state))
+                        .with_name("Sign controller"),
+                ),
+        )
+        .tick(state)
 }
 
 fn claim_target<'a>(state: &mut CreepState) -> ExecutionResult {
     debug!("claiming room");
 
-    let target_room = {
-        state
-            .creep_memory_string(CONQUEST_TARGET)
-            .ok_or_else(|| "no target set")?
-    };
+    let target_room = target_room(state.memory()).ok_or(ExecutionError::NoTarget)?;
 
     let creep = state.creep();
 
@@ -55,13 +51,7 @@ fn claim_target<'a>(state: &mut CreepState) -> ExecutionResult {
         let target_room = WorldPosition::parse_name(target_room)
             .map_err(|e| format!("Got an invalid room name as conquest target {:?}", e))?
             .as_room_center();
-        return move_to_options(
-            creep,
-            &target_room,
-            MoveToOptions {
-                reuse_path: Some(30),
-            },
-        );
+        return move_to_range(state, &target_room, ROOM_RANGE);
     }
 
     let room = creep.room();
@@ -71,28 +61,33 @@ fn claim_target<'a>(state: &mut CreepState) -> ExecutionResult {
 
     let my = my
         .try_into()
-        .map_err(|e| format!("failed to convert 'my' to bool {:?}", e))?;
+        .map_err(|e| ExecutionError::conversion(format!("'my' to bool {:?}", e)))?;
 
     if my {
-        return Err("room is already claimed")?;
+        return Err(ExecutionError::WrongState);
     }
 
-    let controller = room
-        .controller()
-        .ok_or_else(|| format!("room {:?} has no controller", room.name()))?;
+    let controller = room.controller().ok_or(ExecutionError::NoTarget)?;
 
     let result = creep.claim_controller(&controller);
 
     match result {
-        ReturnCode::Ok => Ok(()),
-        ReturnCode::NotInRange => move_to(creep, &controller),
-        _ => Err(format!("failed to claim controller {:?}", result))?,
+        ReturnCode::Ok => Ok(Status::Success),
+        ReturnCode::NotInRange => move_to(state, &controller),
+        _ => Err(result)?,
     }
 }
 
+fn target_room(memory: &CreepMemory) -> Option<&str> {
+    memory
+        .payload::<ConquerorMemory>()
+        .and_then(|payload| payload.target_room.as_ref())
+        .map(|room| room.as_str())
+}
+
 fn set_target<'a>(state: &mut CreepState) -> ExecutionResult {
-    if state.creep_memory_string(CONQUEST_TARGET).is_some() {
-        Err("Creep already has a target")?;
+    if target_room(state.memory()).is_some() {
+        Err(ExecutionError::WrongState)?;
     }
 
     let flag = {
@@ -101,12 +96,15 @@ fn set_target<'a>(state: &mut CreepState) -> ExecutionResult {
             .expansion
             .iter()
             .next()
-            .ok_or_else(|| "can't find a target")?
+            .ok_or(ExecutionError::NoTarget)?
             .clone()
     };
 
-    state.creep_memory_set(CONQUEST_TARGET.into(), flag.to_string().as_str());
+    state
+        .memory_mut()
+        .payload_mut::<ConquerorMemory>()
+        .target_room = Some(flag.to_string().as_str().into());
 
-    Ok(())
+    Ok(Status::Success)
 }
 
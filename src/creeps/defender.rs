@@ -1,17 +1,27 @@
 //! Basic creep to attack enemy creeps in rooms
 //!
-use super::{move_to, CreepState};
+use super::{move_to, move_to_range, CreepState, ROOM_RANGE};
+use crate::flags::{self, FlagCommand};
 use crate::prelude::*;
-use screeps::{find, game::get_object_typed, prelude::*, Creep, ReturnCode};
-
-const ATTACK_TARGET: &'static str = "attack_target";
+use screeps::{find, game::get_object_typed, prelude::*, Creep, ReturnCode, RoomObject};
+use stdweb::unstable::{TryFrom, TryInto};
 
 pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
+    let tasks = [
+        Task::new(|state| attack(state)),
+        Task::new(|state| approach_attack_target(state)),
+        Task::new(|state| rally(state)),
+    ];
+
+    sequence(state, tasks.iter())
+}
+
+fn attack(state: &mut CreepState) -> ExecutionResult {
     let tasks = [
         Task::new(|state| attack_simple(state)),
         Task::new(|state| {
             state.creep().say("⚔️", true);
-            Ok(())
+            Ok(Status::Success)
         }),
     ];
 
@@ -22,8 +32,8 @@ pub fn attack_simple(state: &mut CreepState) -> ExecutionResult {
     if let Some(ref target) = find_target(state) {
         let result = state.creep().attack(target);
         match result {
-            ReturnCode::Ok => return Ok(()),
-            ReturnCode::NotInRange => return move_to(state.creep(), target),
+            ReturnCode::Ok => return Ok(Status::Success),
+            ReturnCode::NotInRange => return move_to(state, target),
             _ => {
                 warn!(
                     "Creep {} failed to attack {} {:?}",
@@ -31,15 +41,68 @@ pub fn attack_simple(state: &mut CreepState) -> ExecutionResult {
                     target.name(),
                     result
                 );
-                Err("Failed to attack")?;
+                Err(result)?;
             }
         }
     }
-    Err("Can't find target to attack")?
+    attack_structure(state)
+}
+
+/// Attack the closest hostile structure, so flagged rooms can be cleared
+fn attack_structure(state: &mut CreepState) -> ExecutionResult {
+    let creep = state.creep();
+    let target = js! {
+        return @{creep}.pos.findClosestByRange(FIND_HOSTILE_STRUCTURES);
+    };
+    let target: Option<RoomObject> = target.try_into().unwrap_or(None);
+    let target = target.ok_or(ExecutionError::NoTarget)?;
+    let result = js! {
+        return @{creep}.attack(@{&target});
+    };
+    let result = ReturnCode::try_from(result)
+        .map_err(|e| ExecutionError::conversion(format!("attack result {:?}", e)))?;
+    match result {
+        ReturnCode::Ok => Ok(Status::Success),
+        ReturnCode::NotInRange => move_to(state, &target),
+        _ => Err(result)?,
+    }
+}
+
+/// Move to the closest room flagged for attack
+fn approach_attack_target(state: &mut CreepState) -> ExecutionResult {
+    let current = state.current_room();
+    let target = state
+        .get_game_state()
+        .attack_targets
+        .iter()
+        .min_by_key(|room| room.dist(current))
+        .cloned()
+        .ok_or(ExecutionError::NoTarget)?;
+    if target == current {
+        Err(ExecutionError::WrongState)?;
+    }
+    move_to_range(state, &target.as_room_center(), ROOM_RANGE)
+}
+
+/// Wait at the rally flag of the room
+fn rally(state: &mut CreepState) -> ExecutionResult {
+    let creep = state.creep();
+    let flag = flags::find_in_room(&creep.room(), FlagCommand::Rally)
+        .into_iter()
+        .next()
+        .ok_or(ExecutionError::NoTarget)?;
+    if creep.pos().in_range_to(&flag, 2) {
+        return Ok(Status::Success);
+    }
+    move_to(state, &flag)
 }
 
 fn find_target(state: &mut CreepState) -> Option<Creep> {
-    if let Some(id) = state.creep_memory_string(ATTACK_TARGET) {
+    let target = state
+        .memory()
+        .payload::<DefenderMemory>()
+        .and_then(|payload| payload.attack_target.as_ref());
+    if let Some(id) = target {
         if let Ok(Some(creep)) = get_object_typed::<Creep>(&id) {
             return Some(creep);
         }
@@ -49,7 +112,10 @@ fn find_target(state: &mut CreepState) -> Option<Creep> {
         .pos()
         .find_closest_by_range(find::HOSTILE_CREEPS)
         .map(|creep| {
-            state.creep_memory_set(ATTACK_TARGET, creep.id());
+            state
+                .memory_mut()
+                .payload_mut::<DefenderMemory>()
+                .attack_target = Some(creep.id());
             creep
         })
 }
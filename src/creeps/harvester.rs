@@ -2,10 +2,11 @@
 //!
 use super::{
     gofer::{self, try_transfer},
-    move_to, CreepState, Role, TARGET,
+    logistics, move_to, traffic, CreepState, Role,
 };
 use crate::prelude::*;
 use screeps::{
+    constants::ResourceType,
     find, game,
     game::get_object_erased,
     objects::{Source, StructureContainer},
@@ -13,52 +14,52 @@ use screeps::{
     ReturnCode,
 };
 use std::collections::HashMap;
-use stdweb::{
-    unstable::{TryFrom, TryInto},
-    Reference,
-};
-
-const HARVEST_TARGET: &'static str = "harvest_target";
+use stdweb::{unstable::TryFrom, Reference};
 
 pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
-    let tasks = [
-        Task::new(|state| attempt_harvest(state, None)).with_name("Attempt harvest"),
-        Task::new(|state| unload(state)).with_name("Attempt unload"),
-        Task::new(|state| attempt_harvest(state, None)).with_name("Attempt harvest"),
-    ];
-
-    sequence(state, tasks.iter())
+    Sequence::new()
+        .with_name("Harvester")
+        .with_child(
+            Task::new(|state| attempt_harvest(state, harvest_source)).with_name("Attempt harvest"),
+        )
+        .with_child(Task::new(|state| unload(state)).with_name("Attempt unload"))
+        .with_child(
+            Task::new(|state| attempt_harvest(state, harvest_source)).with_name("Attempt harvest"),
+        )
+        .tick(state)
 }
 
 pub fn unload<'a>(state: &mut CreepState) -> ExecutionResult {
     let carry_total = state.creep().carry_total();
     if carry_total == 0 {
         trace!("Empty");
-        state.creep_memory_remove(TARGET);
-        Err("empty")?;
+        state.memory_mut().target = None;
+        Err(ExecutionError::Empty)?;
     }
 
     let tasks = [
         Task::new(|state: &mut CreepState| {
             let target = find_unload_target(state).ok_or_else(|| {
-                state.creep_memory_remove(TARGET);
-                "could not find unload target"
+                state.memory_mut().target = None;
+                ExecutionError::NoTarget
             })?;
-            try_transfer::<StructureContainer>(state, &target)
+            try_transfer::<StructureContainer>(state, &target).map_err(|e| {
+                logistics::release(state);
+                e
+            })
         })
         .with_name("Try transfer container"),
+        Task::new(|state: &mut CreepState| hand_over(state)).with_name("Hand over to hauler"),
         Task::new(|state: &mut CreepState| {
-            let n = unsafe {
-                let room = state.creep().room();
-                let state = &mut *state.mut_game_state();
-                state
-                    .count_creeps_in_room(&room)
-                    .get(&Role::Gofer)
-                    .map(|x| *x)
-                    .unwrap_or(0)
-            };
+            let room = state.creep().room();
+            let n = state
+                .mut_game_state()
+                .count_creeps_in_room(&room)
+                .get(&Role::Gofer)
+                .map(|x| *x)
+                .unwrap_or(0);
             if n > 0 {
-                Err("Waiting on gofer")?;
+                Err(ExecutionError::WrongState)?;
             }
             gofer::attempt_unload(state)
         })
@@ -66,90 +67,100 @@ pub fn unload<'a>(state: &mut CreepState) -> ExecutionResult {
     ];
 
     sequence(state, tasks.iter()).map_err(|error| {
-        state.creep_memory_remove(TARGET);
+        logistics::release(state);
         debug!("failed to unload {:?}", error);
         error
     })
 }
 
-fn find_unload_target<'a>(state: &mut CreepState) -> Option<Reference> {
-    read_unload_target(state).or_else(|| {
-        find_container(state).unwrap_or_else(|e| {
-            debug!("Failed to find unload target {:?}", e);
-        });
-        read_unload_target(state)
-    })
+/// Transfer the energy to a creep next to the harvester that reserved its offer
+/// Completes the reservation of that creep
+fn hand_over(state: &mut CreepState) -> ExecutionResult {
+    let creep = state.creep().clone();
+    let id = creep.id();
+    let room = creep.room();
+    let hauler = creep
+        .pos()
+        .find_in_range(find::MY_CREEPS, 1)
+        .into_iter()
+        .find(|other| {
+            let name = other.name();
+            state
+                .mut_game_state()
+                .logistics(&room)
+                .reservation(name.as_str())
+                == Some(id.as_str())
+        })
+        .ok_or(ExecutionError::NoTarget)?;
+    let r = creep.transfer_all(&hauler, ResourceType::Energy);
+    if r != ReturnCode::Ok {
+        Err(r)?;
+    }
+    state
+        .mut_game_state()
+        .logistics(&room)
+        .complete(hauler.name().as_str());
+    Ok(Status::Success)
 }
 
-fn read_unload_target<'a>(state: &mut CreepState) -> Option<Reference> {
-    let target = state.creep_memory_string(TARGET);
-
-    if let Some(target) = target {
-        let target = get_object_erased(target)?;
-        Some(target.as_ref().clone())
-    } else {
-        None
-    }
+/// Reserve the closest container needing energy
+fn find_unload_target<'a>(state: &mut CreepState) -> Option<Reference> {
+    let amount = state.creep().carry_total();
+    let id = logistics::reserve(state, EntryKind::Need, amount, logistics::HARVESTER_NEEDS)?;
+    get_object_erased(id.as_str())
+        .map(|target| target.as_ref().clone())
+        .or_else(|| {
+            logistics::release(state);
+            None
+        })
 }
 
-fn find_container<'a>(state: &mut CreepState) -> ExecutionResult {
-    trace!("Finding new unload target");
-
-    let result = js! {
-        let creep = @{state.creep()};
-        const container = creep.pos.findClosestByRange(FIND_STRUCTURES, {
-            filter: (i) => i.structureType == STRUCTURE_CONTAINER
-                && i.store[RESOURCE_ENERGY] < i.storeCapacity
-        });
-        return container;
-    };
-
-    let container: Option<StructureContainer> = result.try_into().unwrap_or(None);
-    if let Some(container) = container {
-        state.creep_memory_set(TARGET.into(), container.id());
-        Ok(())
-    } else {
-        Err("No container was found")?
-    }
+/// Memory slot of the source harvested by harvesters
+fn harvest_source(memory: &mut CreepMemory) -> &mut Option<String> {
+    &mut memory.payload_mut::<HarvesterMemory>().source
 }
 
-pub fn attempt_harvest<'a>(
+/// `target_memory` selects the memory slot holding the id of the harvested source
+pub fn attempt_harvest(
     state: &mut CreepState,
-    target_memory: Option<&'a str>,
+    target_memory: fn(&mut CreepMemory) -> &mut Option<String>,
 ) -> ExecutionResult {
     trace!("Harvesting");
 
-    let target_memory = target_memory.unwrap_or(HARVEST_TARGET);
     let carry_total = state.creep().carry_total();
     let carry_cap = state.creep().carry_capacity();
 
     if carry_total == carry_cap {
-        state.creep_memory_remove(target_memory);
-        Err("full")?;
+        *target_memory(state.memory_mut()) = None;
+        Err(ExecutionError::Full)?;
     }
 
-    let source =
-        harvest_target(state, target_memory).ok_or_else(|| format!("No harvest target found"))?;
+    let source = harvest_target(state, target_memory).ok_or(ExecutionError::NoTarget)?;
 
-    if state.creep().pos().is_near_to(&source) {
-        let r = state.creep().harvest(&source);
-        if r != ReturnCode::Ok {
-            state.creep_memory_remove(target_memory);
-            debug!("Couldn't harvest: {:?}", r);
-        }
-    } else {
-        move_to(state.creep(), &source)?;
+    if !state.creep().pos().is_near_to(&source) {
+        return move_to(state, &source);
     }
+    let r = state.creep().harvest(&source);
+    if r != ReturnCode::Ok {
+        *target_memory(state.memory_mut()) = None;
+        debug!("Couldn't harvest: {:?}", r);
+        Err(r)?;
+    }
+    traffic::pin(state);
 
     trace!("Harvest finished");
-    Ok(())
+    // Keep harvesting until full
+    Ok(Status::Running)
 }
 
-fn harvest_target<'a>(state: &mut CreepState, target_memory: &'a str) -> Option<Source> {
+fn harvest_target(
+    state: &mut CreepState,
+    target_memory: fn(&mut CreepMemory) -> &mut Option<String>,
+) -> Option<Source> {
     trace!("Setting harvest target");
 
-    let target = state
-        .creep_memory_string(target_memory)
+    let target = target_memory(state.memory_mut())
+        .as_ref()
         .and_then(|id| get_object_erased(id));
 
     if let Some(target) = target {
@@ -157,13 +168,13 @@ fn harvest_target<'a>(state: &mut CreepState, target_memory: &'a str) -> Option<
         return Source::try_from(target.as_ref())
             .map_err(|e| {
                 debug!("Failed to convert target to Source {:?}", e);
-                state.creep_memory_remove(target_memory);
+                *target_memory(state.memory_mut()) = None;
             })
             .ok();
     }
 
     find_harvest_target(state).map(|source| {
-        state.creep_memory_set(target_memory.into(), source.id());
+        *target_memory(state.memory_mut()) = Some(source.id());
         source
     })
 }
@@ -206,7 +217,9 @@ fn harvester_count<'a>(state: &mut CreepState) -> HashMap<String, i32> {
     game::creeps::values().into_iter().for_each(|creep| {
         let target = state
             .get_game_state()
-            .creep_memory_string(CreepName(&creep.name()), HARVEST_TARGET);
+            .creep_memory_get(CreepName(&creep.name()))
+            .and_then(|memory| memory.payload::<HarvesterMemory>())
+            .and_then(|payload| payload.source.as_ref());
         if let Some(target) = target {
             *result.entry(target.to_owned()).or_insert(0) += 1;
         }
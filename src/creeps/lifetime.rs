@@ -0,0 +1,83 @@
+//! Predict the death of the creeps, so their replacements are spawned in time
+//! A creep is replaced once its ticks to live drop below the time it takes to spawn a creep of
+//! the same body and walk it from the spawn to where the creep is working
+//!
+use crate::prelude::*;
+use screeps::{objects::StructureSpawn, prelude::*, Creep, Room};
+use stdweb::unstable::TryFrom;
+
+/// Ticks a spawn takes per body part
+pub const CREEP_SPAWN_TIME: u32 = 3;
+
+/// Ticks to cross a room, used for creeps working outside of their home
+const ROOM_TRAVEL_TIME: u32 = 50;
+
+/// Ticks the creep takes per plain tile
+/// Every part but MOVE adds 2 fatigue per tile and every MOVE part removes 2 per tick
+pub fn ticks_per_tile(n_parts: u32, n_move: u32) -> u32 {
+    let n_move = n_move.max(1);
+    let n_other = n_parts.saturating_sub(n_move);
+    ((n_other + n_move - 1) / n_move).max(1)
+}
+
+/// Ticks between queueing the replacement and it arriving at the work site
+/// `margin` covers the ticks the request waits for a free spawn
+pub fn replacement_lead_time(n_parts: u32, n_move: u32, distance: u32, margin: u32) -> u32 {
+    n_parts * CREEP_SPAWN_TIME + distance * ticks_per_tile(n_parts, n_move) + margin
+}
+
+/// Is it time to spawn the replacement of the creep
+pub fn is_dying(creep: &Creep, home: &Room, spawns: &[StructureSpawn], margin: u32) -> bool {
+    if creep.spawning() {
+        return false;
+    }
+    let n_parts = creep.body().len() as u32;
+    let n_move = count_move_parts(creep);
+    let distance = travel_distance(creep, home, spawns);
+    creep.ticks_to_live() <= replacement_lead_time(n_parts, n_move, distance, margin)
+}
+
+/// The part type of a Bodypart is not exposed by the api
+fn count_move_parts(creep: &Creep) -> u32 {
+    let n_move = js! {
+        return @{creep.as_ref()}.body.filter((part) => part.type == MOVE).length;
+    };
+    u32::try_from(n_move).unwrap_or(0)
+}
+
+/// Tiles from the closest spawn of the home room to the creep
+fn travel_distance(creep: &Creep, home: &Room, spawns: &[StructureSpawn]) -> u32 {
+    let room = creep.room();
+    if room.name() != home.name() {
+        let rooms = WorldPosition::from(&room).dist(WorldPosition::from(home)) as u32;
+        return rooms * ROOM_TRAVEL_TIME;
+    }
+    let pos = creep.pos();
+    spawns
+        .iter()
+        .map(|spawn| spawn.pos().get_range_to(&pos))
+        .min()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks_per_tile() {
+        // [MOVE, WORK, CARRY, WORK]
+        assert_eq!(ticks_per_tile(4, 1), 3);
+        assert_eq!(ticks_per_tile(4, 2), 1);
+        assert_eq!(ticks_per_tile(2, 2), 1);
+        // Creeps without MOVE parts can not walk, but the estimate stays finite
+        assert_eq!(ticks_per_tile(5, 0), 4);
+    }
+
+    #[test]
+    fn test_replacement_arrives_as_the_creep_expires() {
+        // 12 parts spawn in 36 ticks, 20 tiles take 40 ticks at 2 ticks per tile
+        assert_eq!(replacement_lead_time(12, 4, 20, 5), 36 + 40 + 5);
+        assert_eq!(replacement_lead_time(2, 1, 0, 0), 6);
+    }
+}
@@ -0,0 +1,199 @@
+//! Post the energy needs and offers of the rooms and reserve them for the haulers
+//! See `state::logistics` for the broker
+//!
+use super::{CreepState, Role};
+use crate::prelude::*;
+use screeps::{
+    constants::{find, ResourceType},
+    game,
+    objects::{CanStoreEnergy, HasStore, Structure},
+    prelude::*,
+    Room,
+};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+pub const SPAWN_NEED: i8 = 4;
+pub const TOWER_NEED: i8 = 3;
+pub const EXTENSION_NEED: i8 = 2;
+/// Upgraders and workers topped up while they work
+pub const CREEP_NEED: i8 = 1;
+pub const STORAGE_NEED: i8 = 0;
+/// Only filled by the harvesters
+pub const CONTAINER_NEED: i8 = -1;
+
+pub const TOMBSTONE_OFFER: i8 = 2;
+/// Full harvesters idle until their energy is taken
+pub const CREEP_OFFER: i8 = 2;
+pub const CONTAINER_OFFER: i8 = 1;
+pub const STORAGE_OFFER: i8 = 0;
+
+/// Needs the gofers deliver to, containers are left to the harvesters
+pub const HAULING_NEEDS: Range<i8> = STORAGE_NEED..std::i8::MAX;
+/// Needs the harvesters unload to
+pub const HARVESTER_NEEDS: Range<i8> = CONTAINER_NEED..STORAGE_NEED;
+/// Offers the gofers take from, taking from the storage would only put it back
+pub const HAULING_OFFERS: Range<i8> = CONTAINER_OFFER..std::i8::MAX;
+/// Offers the workers and upgraders take from
+pub const WITHDRAW_OFFERS: Range<i8> = STORAGE_OFFER..std::i8::MAX;
+
+/// Post the entries of the structures, tombstones and creeps of the room
+pub fn post_entries(
+    broker: &mut LogisticsBroker,
+    room: &Room,
+    memories: &BTreeMap<String, CreepMemory>,
+) {
+    for structure in room.find(find::STRUCTURES) {
+        match structure {
+            Structure::Spawn(ref s) => post_need(broker, s, SPAWN_NEED),
+            Structure::Extension(ref s) => post_need(broker, s, EXTENSION_NEED),
+            Structure::Tower(ref s) => post_need(broker, s, TOWER_NEED),
+            Structure::Storage(ref s) => {
+                let pos = position(s);
+                let free = s.store_capacity() - s.store_total();
+                broker.post(EntryKind::Need, &s.id(), free, STORAGE_NEED, pos);
+                broker.post(EntryKind::Offer, &s.id(), s.energy(), STORAGE_OFFER, pos);
+            }
+            Structure::Container(ref s) => {
+                let pos = position(s);
+                let free = s.store_capacity() - s.store_total();
+                broker.post(EntryKind::Need, &s.id(), free, CONTAINER_NEED, pos);
+                broker.post(EntryKind::Offer, &s.id(), s.energy(), CONTAINER_OFFER, pos);
+            }
+            _ => {}
+        }
+    }
+    for tombstone in room.find(find::TOMBSTONES) {
+        let energy = tombstone.store_of(ResourceType::Energy);
+        if energy > 0 {
+            let pos = position(&tombstone);
+            broker.post(
+                EntryKind::Offer,
+                &tombstone.id(),
+                energy,
+                TOMBSTONE_OFFER,
+                pos,
+            );
+        }
+    }
+    for creep in room.find(find::MY_CREEPS) {
+        let memory = match memories.get(creep.name().as_str()) {
+            Some(memory) => memory,
+            None => continue,
+        };
+        let energy = creep.carry_of(ResourceType::Energy);
+        if let Some((kind, amount, priority)) = creep_entry(memory, energy, creep.carry_capacity())
+        {
+            broker.post(kind, &creep.id(), amount, priority, position(&creep));
+        }
+    }
+}
+
+/// Upgraders and workers at work need the energy they have room for
+/// Full harvesters with no container to unload to offer their energy
+/// Miners only mine from a container so they never hold energy to offer
+fn creep_entry(memory: &CreepMemory, energy: u32, capacity: u32) -> Option<(EntryKind, u32, i8)> {
+    match memory.role? {
+        Role::Upgrader | Role::Worker if !memory.loading && energy < capacity => {
+            Some((EntryKind::Need, capacity - energy, CREEP_NEED))
+        }
+        Role::Harvester if energy > 0 && energy == capacity && memory.target.is_none() => {
+            Some((EntryKind::Offer, energy, CREEP_OFFER))
+        }
+        _ => None,
+    }
+}
+
+fn post_need<T>(broker: &mut LogisticsBroker, structure: &T, priority: i8)
+where
+    T: CanStoreEnergy + HasId,
+{
+    let free = structure.energy_capacity() - structure.energy();
+    let pos = position(structure);
+    broker.post(EntryKind::Need, &structure.id(), free, priority, pos);
+}
+
+fn position<T: HasPosition>(target: &T) -> (u8, u8) {
+    let pos = target.pos();
+    (pos.x() as u8, pos.y() as u8)
+}
+
+/// Reserve an entry in the room of the creep and set it as the creep's target
+/// `amount` is the energy the creep delivers or takes
+pub fn reserve(
+    state: &mut CreepState,
+    kind: EntryKind,
+    amount: u32,
+    priorities: Range<i8>,
+) -> Option<String> {
+    let creep = state.creep().clone();
+    let name = creep.name();
+    let pos = position(&creep);
+    let id = state.mut_game_state().logistics(&creep.room()).reserve(
+        kind,
+        name.as_str(),
+        amount,
+        priorities,
+        pos,
+        game::time(),
+    )?;
+    state.memory_mut().target = Some(id.clone());
+    Some(id)
+}
+
+/// The creep delivered or took the energy of its reservation
+pub fn complete(state: &mut CreepState) {
+    let creep = state.creep().clone();
+    state
+        .mut_game_state()
+        .logistics(&creep.room())
+        .complete(creep.name().as_str());
+    state.memory_mut().target = None;
+}
+
+/// The creep gave up on its reservation
+pub fn release(state: &mut CreepState) {
+    let creep = state.creep().clone();
+    state
+        .mut_game_state()
+        .logistics(&creep.room())
+        .release(creep.name().as_str());
+    state.memory_mut().target = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(role: Role, loading: bool, target: Option<&str>) -> CreepMemory {
+        CreepMemory {
+            role: Some(role),
+            loading,
+            target: target.map(|t| t.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_creep_entries() {
+        let upgrader = memory(Role::Upgrader, false, None);
+        assert_eq!(
+            creep_entry(&upgrader, 20, 50),
+            Some((EntryKind::Need, 30, CREEP_NEED))
+        );
+        // Loading creeps fetch the energy themselves
+        let loading = memory(Role::Worker, true, None);
+        assert_eq!(creep_entry(&loading, 20, 50), None);
+
+        let harvester = memory(Role::Harvester, false, None);
+        assert_eq!(
+            creep_entry(&harvester, 50, 50),
+            Some((EntryKind::Offer, 50, CREEP_OFFER))
+        );
+        // On its way to a container
+        let unloading = memory(Role::Harvester, false, Some("container"));
+        assert_eq!(creep_entry(&unloading, 50, 50), None);
+        assert_eq!(creep_entry(&harvester, 20, 50), None);
+        assert_eq!(creep_entry(&memory(Role::Miner, false, None), 50, 50), None);
+    }
+}
@@ -3,48 +3,23 @@
 //!
 
 use super::{
-    approach_target_room, gofer, harvester, update_scout_info, upgrader, CreepState, HOME_ROOM,
-    LOADING, TARGET, TASK,
+    approach_target_room, gofer, harvester, update_scout_info, upgrader, CreepState,
+    SCOUT_INFO_INTERVAL,
 };
 use crate::prelude::*;
 use crate::state::RoomIFF;
-use num::FromPrimitive;
 use screeps::prelude::*;
 
-const HARVEST_TARGET_ROOM: &'static str = "harvest_target_room";
-
-#[derive(Debug, Clone, Copy, FromPrimitive, ToPrimitive)]
-#[repr(u8)]
-enum LrhState {
-    Idle = 0,
-    Loading = 1,
-    Unloading = 2,
-}
-
 pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
-    let last_task = state.creep_memory_i64(TASK).unwrap_or(0);
-    let last_task = LrhState::from_u32(last_task as u32).unwrap_or(LrhState::Idle);
-
-    let mut priorities = [0; 3];
-    priorities[last_task as usize] += 1;
-
-    let mut tasks = [
+    let tasks = [
         Task::new(|state| load(state))
             .with_name("Load")
-            .with_state_save(LrhState::Loading)
-            .with_priority(priorities[LrhState::Loading as usize])
             .with_required_bucket(2000),
-        Task::new(|state| unload(state))
-            .with_name("Unload")
-            .with_state_save(LrhState::Unloading)
-            .with_priority(priorities[LrhState::Unloading as usize]),
-        Task::new(|state| harvester::unload(state))
-            .with_name("Harvester unload")
-            .with_priority(-1),
-        Task::new(|state| upgrader::attempt_upgrade(state)).with_priority(-2),
+        Task::new(|state| unload(state)).with_name("Unload"),
+        Task::new(|state| harvester::unload(state)).with_name("Harvester unload"),
+        Task::new(|state| upgrader::attempt_upgrade(state)).with_name("Attempt upgrade"),
     ];
 
-    sorted_by_priority(&mut tasks);
     sequence(state, tasks.iter())
 }
 
@@ -52,59 +27,73 @@ pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
 fn load<'a>(state: &mut CreepState) -> ExecutionResult {
     trace!("Loading");
 
-    if !state.creep_memory_bool(LOADING).unwrap_or(false) {
-        Err("not loading")?;
+    if !state.memory().loading {
+        Err(ExecutionError::WrongState)?;
     }
     let creep = state.creep();
     if creep.carry_total() == creep.carry_capacity() {
-        state.creep_memory_set(LOADING.into(), false);
-        state.creep_memory_remove(TARGET);
-        Err("full")?;
+        let memory = state.memory_mut();
+        memory.loading = false;
+        memory.target = None;
+        Err(ExecutionError::Full)?;
     }
-    let tasks = [
-        Task::new(|state| approach_target_room(state, HARVEST_TARGET_ROOM))
+    let tasks: [&dyn BtNode<CreepState>; 3] = [
+        &Task::new(|state| approach_target_room(state, target_room))
             .with_name("Approach target room"),
-        Task::new(|state| set_target_room(state)).with_name("Set target room"),
-        Task::new(|state| {
-            update_scout_info(state)?;
-            Err("continue")?
-        })
-        .with_name("Update scout info"),
-        Task::new(|state| harvester::attempt_harvest(state, Some(TARGET)))
-            .with_name("Attempt harvest"),
+        &Task::new(|state| set_target_room(state)).with_name("Set target room"),
+        &Parallel::new()
+            .with_name("Harvest")
+            .with_child(Succeeder::new(Cooldown::new(
+                SCOUT_INFO_INTERVAL,
+                Task::new(|state: &mut CreepState| update_scout_info(state))
+                    .with_name("Update scout info"),
+            )))
+            .with_child(
+                Task::new(|state: &mut CreepState| {
+                    harvester::attempt_harvest(state, |memory| &mut memory.target)
+                })
+                .with_name("Attempt harvest"),
+            ),
     ];
 
     sequence(state, tasks.iter())
 }
 
+fn target_room(memory: &CreepMemory) -> Option<&str> {
+    memory
+        .payload::<LrhMemory>()
+        .and_then(|payload| payload.target_room.as_ref())
+        .map(|room| room.as_str())
+}
+
 fn set_target_room<'a>(state: &'a mut CreepState) -> ExecutionResult {
-    {
-        let target = state.creep_memory_string(HARVEST_TARGET_ROOM);
-        if target.is_some() {
-            Err("Already has a target")?;
-        }
+    if target_room(state.memory()).is_some() {
+        Err(ExecutionError::WrongState)?;
     }
 
-    let room = {
-        let creep = state.creep();
-        creep.room()
-    };
-    let room = WorldPosition::from(room);
+    let creep = state.creep().clone();
+    let room = WorldPosition::from(creep.room());
     let neighbours = room.neighbours_in_vectors();
 
     let target = {
-        let gs: &mut GameState = unsafe { &mut *state.mut_game_state() };
+        let gs = state.mut_game_state();
         let counts: &mut _ = gs
             .long_range_harvesters
             .entry(room)
             .or_insert([0; 4]);
 
         let scout_intel = &gs.scout_intel;
+        let remote_mining = &gs.remote_mining;
+        // Rooms flagged for remote mining override the scout intel
+        let flagged = neighbours.iter().any(|wp| remote_mining.contains(wp));
 
         let (i, target) = neighbours
             .iter()
             .enumerate()
             .filter(|(_, wp)| {
+                if flagged {
+                    return remote_mining.contains(wp);
+                }
                 scout_intel
                     .get(&wp)
                     .map(|int| match int.iff {
@@ -117,35 +106,38 @@ fn set_target_room<'a>(state: &'a mut CreepState) -> ExecutionResult {
             .ok_or_else(|| {
                 warn!(
                     "Failed to find target room of LRH {:?} in room {:?}",
-                    state.creep().name(),
-                    state.creep().room().name()
+                    creep.name(),
+                    creep.room().name()
                 );
-                "Failed to find a target room"
+                ExecutionError::NoTarget
             })?;
 
         counts[i] += 1;
         target
     };
 
-    state.creep_memory_set(HARVEST_TARGET_ROOM.into(), target.to_string().as_str());
+    state.memory_mut().payload_mut::<LrhMemory>().target_room =
+        Some(target.to_string().as_str().into());
 
-    Ok(())
+    Ok(Status::Success)
 }
 
 /// Unload energy in the parent room
 fn unload<'a>(state: &mut CreepState) -> ExecutionResult {
     trace!("Unloading");
 
-    if state.creep_memory_bool(LOADING).unwrap_or(false) {
-        Err("loading")?;
+    if state.memory().loading {
+        Err(ExecutionError::WrongState)?;
     }
     if state.creep().carry_total() == 0 {
-        state.creep_memory_set(LOADING.into(), true);
-        state.creep_memory_remove(TARGET);
-        Err("empty")?;
+        let memory = state.memory_mut();
+        memory.loading = true;
+        memory.target = None;
+        Err(ExecutionError::Empty)?;
     }
     let tasks = [
-        Task::new(|state| approach_target_room(state, HOME_ROOM)).with_name("Approach target room"),
+        Task::new(|state| approach_target_room(state, CreepMemory::home))
+            .with_name("Approach target room"),
         Task::new(|state| gofer::attempt_unload(state)).with_name("Attempt unload"),
     ];
 
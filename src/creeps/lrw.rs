@@ -1,11 +1,11 @@
 //! Long Range Worker
 //! Used to work on other rooms
 //!
-use super::{move_to_options, update_scout_info, worker, CreepState, MoveToOptions};
+use super::{
+    move_to_range, update_scout_info, worker, CreepState, ROOM_RANGE, SCOUT_INFO_INTERVAL,
+};
 use crate::prelude::*;
 
-const TARGET_ROOM: &'static str = "target_room";
-
 pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
     Task::new(_run)
         .with_required_bucket(300)
@@ -14,52 +14,50 @@ pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
 }
 
 fn _run(state: &mut CreepState) -> ExecutionResult {
-    let tasks = [
-        Task::new(|state| {
-            update_scout_info(state).unwrap_or_else(|e| {
-                error!("Failed to update scout info {:?}", e);
-            });
-            Err("continue")?
-        })
-        .with_name("Update scout info"),
-        Task::new(|state| approach_target_room(state)).with_name("Approach target room"),
-        Task::new(|state| set_target(state)).with_name("Set target"),
-        Task::new(|state| worker::run(state)).with_name("Worker run"),
-    ];
-
-    sequence(state, tasks.iter())
+    Parallel::new()
+        .with_name("LRW")
+        .with_child(Succeeder::new(Cooldown::new(
+            SCOUT_INFO_INTERVAL,
+            Task::new(|state| update_scout_info(state)).with_name("Update scout info"),
+        )))
+        .with_child(
+            Sequence::new()
+                .with_name("Work")
+                .with_child(
+                    Task::new(|state| approach_target_room(state))
+                        .with_name("Approach target room"),
+                )
+                .with_child(Task::new(|state| set_target(state)).with_name("Set target"))
+                .with_child(Task::new(|state| worker::run(state)).with_name("Worker run")),
+        )
+        .tick(state)
 }
 
 fn approach_target_room<'a>(state: &mut CreepState) -> ExecutionResult {
-    let target_room = {
-        state
-            .creep_memory_string(TARGET_ROOM)
-            .ok_or_else(|| "no target set")?
-    };
-
-    let creep = state.creep();
+    let target_room = target_room(state.memory()).ok_or(ExecutionError::NoTarget)?;
 
     let arrived = state.current_room().to_string().as_str() == target_room;
 
     if arrived {
-        Err("Already in the room")?;
+        Err(ExecutionError::WrongState)?;
     }
     let target_room = WorldPosition::parse_name(target_room)
         .map_err(|e| format!("Got an invalid room name as conquest target {:?}", e))?
         .as_room_center();
-    move_to_options(
-        creep,
-        &target_room,
-        MoveToOptions {
-            reuse_path: Some(30),
-        },
-    )
+    move_to_range(state, &target_room, ROOM_RANGE)
+}
+
+fn target_room(memory: &CreepMemory) -> Option<&str> {
+    memory
+        .payload::<LrwMemory>()
+        .and_then(|payload| payload.target_room.as_ref())
+        .map(|room| room.as_str())
 }
 
 fn set_target<'a>(state: &mut CreepState) -> ExecutionResult {
-    if state.creep_memory_string(TARGET_ROOM).is_some() {
+    if target_room(state.memory()).is_some() {
         trace!("has target");
-        Err("Creep already has a target")?;
+        Err(ExecutionError::WrongState)?;
     }
     let flag = {
         state
@@ -67,12 +65,13 @@ fn set_target<'a>(state: &mut CreepState) -> ExecutionResult {
             .expansion
             .iter()
             .next()
-            .ok_or_else(|| "can't find a target")?
+            .ok_or(ExecutionError::NoTarget)?
             .clone()
     };
 
-    state.creep_memory_set(TARGET_ROOM.into(), flag.to_string().as_str());
+    state.memory_mut().payload_mut::<LrwMemory>().target_room =
+        Some(flag.to_string().as_str().into());
 
-    Ok(())
+    Ok(Status::Success)
 }
 
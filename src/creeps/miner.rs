@@ -0,0 +1,103 @@
+//! Static miners park on the container next to a source and harvest it until they die
+//! The harvested energy drops into the container, hauling it is left to the gofers
+//!
+use super::{move_to_range, traffic, CreepState};
+use crate::prelude::*;
+use screeps::{
+    find, game,
+    game::get_object_typed,
+    objects::{Source, Structure, StructureContainer},
+    prelude::*,
+    ReturnCode,
+};
+use std::collections::HashSet;
+
+/// Energy a WORK part harvests per tick
+const HARVEST_POWER: u32 = 2;
+/// Ticks a source takes to regenerate its energy
+const ENERGY_REGEN_TIME: u32 = 300;
+
+/// WORK parts draining a source of the capacity in one regeneration cycle
+pub fn work_parts_to_drain(energy_capacity: u32) -> u32 {
+    let per_part = HARVEST_POWER * ENERGY_REGEN_TIME;
+    (energy_capacity + per_part - 1) / per_part
+}
+
+pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
+    Task::new(|state| attempt_mine(state))
+        .with_name("Attempt mine")
+        .tick(state)
+}
+
+fn attempt_mine(state: &mut CreepState) -> ExecutionResult {
+    let (container, source) = mining_target(state).ok_or(ExecutionError::NoTarget)?;
+
+    if !state.creep().pos().in_range_to(&container, 0) {
+        return move_to_range(state, &container, 0);
+    }
+
+    let res = state.creep().harvest(&source);
+    match res {
+        // The source is empty until it regenerates
+        ReturnCode::Ok | ReturnCode::NotEnough => {
+            traffic::pin(state);
+            Ok(Status::Running)
+        }
+        _ => {
+            debug!("Couldn't mine: {:?}", res);
+            Err(res)?
+        }
+    }
+}
+
+/// The container the miner parks on and the source next to it
+/// Assigned on the first run and kept until the container is destroyed
+fn mining_target(state: &mut CreepState) -> Option<(StructureContainer, Source)> {
+    let assigned = state
+        .memory()
+        .payload::<MinerMemory>()
+        .and_then(|payload| payload.container.as_ref())
+        .and_then(|id| get_object_typed::<StructureContainer>(id).unwrap_or(None));
+    let container = match assigned {
+        Some(container) => container,
+        None => {
+            let container = find_free_container(state)?;
+            state.memory_mut().payload_mut::<MinerMemory>().container = Some(container.id());
+            container
+        }
+    };
+    let source = container
+        .pos()
+        .find_in_range(find::SOURCES, 1)
+        .into_iter()
+        .next()?;
+    Some((container, source))
+}
+
+/// Closest container next to a source of the room without a miner
+fn find_free_container(state: &CreepState) -> Option<StructureContainer> {
+    let taken = game::creeps::values()
+        .into_iter()
+        .filter_map(|creep| {
+            state
+                .get_game_state()
+                .creep_memory_get(CreepName(&creep.name()))
+                .and_then(|memory| memory.payload::<MinerMemory>())
+                .and_then(|payload| payload.container.clone())
+        })
+        .collect::<HashSet<_>>();
+
+    let pos = state.creep().pos();
+    state
+        .creep()
+        .room()
+        .find(find::SOURCES)
+        .into_iter()
+        .flat_map(|source| source.pos().find_in_range(find::STRUCTURES, 1))
+        .filter_map(|s| match s {
+            Structure::Container(container) => Some(container),
+            _ => None,
+        })
+        .filter(|container| !taken.contains(&container.id()))
+        .min_by_key(|container| container.pos().get_range_to(&pos))
+}
@@ -1,3 +1,5 @@
+pub mod lifetime;
+pub mod logistics;
 pub mod roles;
 pub mod spawn_info;
 
@@ -7,14 +9,18 @@ mod gofer;
 mod harvester;
 mod lrh;
 mod lrw;
+mod miner;
+mod pathing;
 mod repairer;
 mod scout;
+mod traffic;
 mod upgrader;
 mod worker;
 
+pub use self::pathing::{move_to, move_to_range, ROOM_RANGE};
 pub use self::roles::Role;
 use crate::prelude::*;
-use crate::USERNAME;
+use crate::stats::is_trace_tick;
 use screeps::{
     constants::{find, ResourceType},
     game::{self, get_object_erased, get_object_typed},
@@ -25,104 +31,168 @@ use screeps::{
     prelude::*,
     ReturnCode, Room,
 };
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
 use stdweb::{
     unstable::{TryFrom, TryInto},
     Reference,
 };
 
-pub const HOME_ROOM: &'static str = "home";
-pub const TARGET: &'static str = "target";
-pub const CREEP_ROLE: &'static str = "role";
-pub const LOADING: &'static str = "loading";
-pub const TASK: &'static str = "task";
-
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CreepExecutionStats {
     working_creeps: u16,
     idle_creeps: u16,
+    /// Structure: error category -> number of idle creeps
+    #[serde(default)]
+    idle_causes: BTreeMap<String, u16>,
+    /// Creeps not executed because the CPU deadline was reached
+    #[serde(default)]
+    skipped_creeps: u16,
     total_execution_time: f32,
 }
 
+/// Run the creeps until the CPU deadline of the subsystem is reached
+/// Critical creeps run first, the rest start from the first creep skipped in the previous tick
 pub fn run(state: &mut GameState) -> ExecutionResult {
     let start = game::cpu::get_used();
 
-    screeps::game::creeps::values()
+    let trace = is_trace_tick(state);
+    let mut traced_roles = BTreeSet::new();
+
+    let (critical, mut others): (Vec<_>, Vec<_>) = screeps::game::creeps::values()
         .into_iter()
-        .for_each(|creep| {
-            let mut state = CreepState::new(creep, state);
-            run_creep(&mut state).unwrap_or(())
+        .partition(|creep| {
+            state
+                .creep_memory_role(CreepName(&creep.name()))
+                .map(|role| role.is_critical())
+                .unwrap_or(false)
         });
+    let offset = if others.is_empty() {
+        0
+    } else {
+        state.creep_offset as usize % others.len()
+    };
+    others.rotate_left(offset);
+    let n_critical = critical.len();
+    let n_creeps = n_critical + others.len();
+
+    for (i, creep) in critical.into_iter().chain(others.into_iter()).enumerate() {
+        let used = game::cpu::get_used() as f32;
+        if state.cpu_deadline.map(|d| used >= d).unwrap_or(false) {
+            warn!(
+                "Skipping {} creeps, CPU used: {:.2} deadline: {:.2?}",
+                n_creeps - i,
+                used,
+                state.cpu_deadline
+            );
+            state.creep_stats.skipped_creeps = (n_creeps - i) as u16;
+            if i > n_critical {
+                state.creep_offset = (offset + i - n_critical) as u32;
+            }
+            break;
+        }
+
+        let mut creep_state = CreepState::new(creep, state);
+        // Sample one creep per role
+        let sample = trace
+            && creep_state
+                .memory()
+                .role
+                .map(|role| traced_roles.insert(role))
+                .unwrap_or(false);
+        if sample {
+            creep_state.set_tracer(Tracer::new(game::cpu::get_used));
+        }
+        run_creep(&mut creep_state).map(|_| ()).unwrap_or(());
+        if let Some(tracer) = creep_state.take_tracer() {
+            let name = creep_state.creep_name().0.to_owned();
+            creep_state
+                .mut_game_state()
+                .traces
+                .insert(name, tracer.into_entries());
+        }
+    }
+
+    traffic::run(state);
 
     let end = game::cpu::get_used();
 
     state.creep_stats.total_execution_time = (end - start) as f32;
 
-    Ok(())
+    Ok(Status::Success)
 }
 
 fn run_creep(state: &mut CreepState) -> ExecutionResult {
     debug!("Running creep {}", state.creep_name().0);
 
     if state.creep().spawning() {
-        return Ok(());
+        return Ok(Status::Success);
     }
     let tasks = [
         Task::new(|state: &mut CreepState| {
             run_role(state)
                 .map_err(|e| {
                     debug!("Recording failed run {:?}", e);
-                    unsafe {
-                        (*state.mut_game_state()).creep_stats.idle_creeps += 1;
-                    }
+                    let stats = &mut state.mut_game_state().creep_stats;
+                    stats.idle_creeps += 1;
+                    *stats
+                        .idle_causes
+                        .entry(e.category().to_owned())
+                        .or_insert(0) += 1;
                     state.creep().say("💤", false);
                     e
                 })
-                .map(|_| {
+                .map(|status| {
                     debug!("Recording successful run");
-                    unsafe {
-                        (*state.mut_game_state()).creep_stats.working_creeps += 1;
-                    }
+                    state.mut_game_state().creep_stats.working_creeps += 1;
+                    status
                 })
         }),
         Task::new(|state: &mut CreepState| {
-            let gs = state.mut_game_state();
-            unsafe { initialize_creep(&mut *gs, state.creep()) }
+            let creep = state.creep().clone();
+            initialize_creep(state.mut_game_state(), &creep)
         }),
     ];
 
-    sequence(state, tasks.iter())
+    let result = sequence(state, tasks.iter());
+    state.save_tree_state();
+    result
 }
 
 pub fn initialize_creep<'a>(state: &'a mut GameState, creep: &'a Creep) -> ExecutionResult {
     assign_role(state, &creep).ok_or_else(|| "Failed to find a role for creep")?;
     let memory = state.creep_memory_entry(CreepName(&creep.name()));
-    memory.insert(HOME_ROOM.into(), creep.room().name().into());
-    Ok(())
+    memory.home = Some(creep.room().name());
+    Ok(Status::Success)
 }
 
+/// The role is written to the memory of the creep when it's spawned
+/// Creeps that lost their memory get it back from their name, `<role>_<number>`
 fn assign_role<'a>(state: &'a mut GameState, creep: &'a Creep) -> Option<Role> {
-    trace!("Assigning role to {}", creep.name());
+    let name = creep.name();
+    trace!("Assigning role to {}", name);
 
-    if state
-        .creep_memory_role(CreepName(&creep.name()), CREEP_ROLE)
-        .is_some()
-    {
+    if state.creep_memory_role(CreepName(&name)).is_some() {
         trace!("Already has a role");
         None?;
     }
 
-    let result = roles::next_role(state, &creep.room()).or_else(|| {
-        warn!("Room is full");
-        None
-    })?;
+    let result = name
+        .split('_')
+        .next()
+        .and_then(|prefix| prefix.parse().ok())
+        .or_else(|| {
+            warn!("Creep {} has no role", name);
+            None
+        })?;
 
-    let memory = state.creep_memory_entry(CreepName(&creep.name()));
-    memory.insert(CREEP_ROLE.to_string(), (result as i64).into());
+    let memory = state.creep_memory_entry(CreepName(&name));
+    memory.role = Some(result);
     Some(result)
 }
 
 fn run_role<'a>(state: &'a mut CreepState) -> ExecutionResult {
-    let role = state.creep_memory_role(CREEP_ROLE).ok_or_else(|| {
+    let role = state.memory().role.ok_or_else(|| {
         let error = "failed to read creep role";
         error!("{}", error);
         error
@@ -131,155 +201,119 @@ fn run_role<'a>(state: &'a mut CreepState) -> ExecutionResult {
     roles::run_role(state, role)
 }
 
-pub fn move_to<'a, T>(creep: &'a Creep, target: &'a T) -> ExecutionResult
-where
-    T: screeps::HasPosition,
-{
-    let res = js! {
-        const creep = @{creep};
-        const target = @{target.pos()};
-        return creep.moveTo(target, {reusePath: 10});
-    };
-    let res =
-        ReturnCode::try_from(res).map_err(|e| format!("Failed to convert move result {:?}", e))?;
-    match res {
-        ReturnCode::Ok | ReturnCode::Tired => Ok(()),
-        _ => {
-            debug!("Move failed {:?}", res);
-            Err("Move failed")?
-        }
-    }
-}
-
-pub struct MoveToOptions {
-    reuse_path: Option<i32>,
-}
-
-pub fn move_to_options<'a, T>(
-    creep: &'a Creep,
-    target: &'a T,
-    options: MoveToOptions,
-) -> ExecutionResult
-where
-    T: screeps::HasPosition,
-{
-    let reuse_path = options.reuse_path;
-    let res = js! {
-        const creep = @{creep};
-        const target = @{target.pos()};
-        const reusePath = @{reuse_path};
-        return creep.moveTo(target, {reusePath: reusePath});
-    };
-    let res =
-        ReturnCode::try_from(res).map_err(|e| format!("Failed to convert move result {:?}", e))?;
-    match res {
-        ReturnCode::Ok | ReturnCode::Tired => Ok(()),
-        _ => {
-            debug!("Move failed {:?}", res);
-            Err("Move failed")?
-        }
-    }
-}
-
-/// Find and pick up energy from the ground
+/// Find and pick up the resources accepted by `accept` from the ground
 /// # Contracts & Side effects
 /// Required the `loading` flag to be set to true
 /// If the creep is full sets the `loading` flag to false
-pub fn pickup_energy(state: &mut CreepState) -> ExecutionResult {
-    if !state.creep_memory_bool(LOADING).unwrap_or(false) {
-        Err("not loading")?;
+pub fn pickup_resources<F>(state: &mut CreepState, accept: F) -> ExecutionResult
+where
+    F: Fn(ResourceType) -> bool,
+{
+    if !state.memory().loading {
+        Err(ExecutionError::WrongState)?;
     }
 
     if state.creep().carry_total() == state.creep().carry_capacity() {
-        state.creep_memory_set(LOADING.into(), false);
-        state.creep_memory_remove(TARGET);
-        Err("full")?;
+        let memory = state.memory_mut();
+        memory.loading = false;
+        memory.target = None;
+        Err(ExecutionError::Full)?;
     }
 
     let target = state
-        .creep_memory_string(TARGET)
+        .memory()
+        .target()
         .and_then(|id| get_object_typed::<Resource>(id).unwrap_or(None))
+        .filter(|resource| accept(resource.resource_type()))
         .or_else(|| {
-            find_dropped_energy(state.creep()).map(|target| {
-                let id = target.id();
-                state.creep_memory_set(TARGET.into(), id);
+            find_dropped_resource(state.creep(), &accept).map(|target| {
+                state.memory_mut().target = Some(target.id());
                 target
             })
         })
         .ok_or_else(|| {
-            state.creep_memory_remove(TARGET);
-            "Can't find energy source"
+            state.memory_mut().target = None;
+            ExecutionError::NoTarget
         })?;
 
     let tasks = [
         Task::new(|state: &mut WrappedState<Resource, CreepState>| {
             match state.state.creep().pickup(&state.item) {
-                ReturnCode::Ok => Ok(()),
-                _ => Err("Can't pick up")?,
+                ReturnCode::Ok => Ok(Status::Success),
+                result => Err(result)?,
             }
         }),
         Task::new(|state: &mut WrappedState<Resource, CreepState>| {
-            move_to(state.state.creep(), &state.item)
+            move_to(state.state, &state.item)
         }),
         Task::new(|state: &mut WrappedState<Resource, CreepState>| {
-            state.state.creep_memory_remove(TARGET);
-            Ok(())
+            state.state.memory_mut().target = None;
+            Ok(Status::Success)
         }),
     ];
 
     let mut state = WrappedState::new(target, state);
 
-    sequence(&mut state, tasks.iter()).map_err(|_| {
-        state.state.creep_memory_remove(TARGET);
-        "can't pick up energy".into()
+    sequence(&mut state, tasks.iter()).map_err(|e| {
+        debug!("Can't pick up resources {}", e);
+        state.state.memory_mut().target = None;
+        e
     })
 }
 
-pub fn find_dropped_energy(creep: &Creep) -> Option<Resource> {
+/// The largest pile of the resources accepted by `accept`
+pub fn find_dropped_resource<F>(creep: &Creep, accept: &F) -> Option<Resource>
+where
+    F: Fn(ResourceType) -> bool,
+{
     creep
         .room()
         .find(find::DROPPED_RESOURCES)
         .into_iter()
-        .filter(|resource| resource.resource_type() == ResourceType::Energy)
+        .filter(|resource| accept(resource.resource_type()))
         .max_by_key(|r| r.amount())
 }
 
-/// Retreive energy from a Container
+/// Retreive energy from a Tombstone, Storage or Container
 /// # Contracts & Side effects
 /// Required the `loading` flag to be set to true
 /// If the creep is full sets the `loading` flag to false
 pub fn withdraw_energy<'a>(state: &'a mut CreepState) -> ExecutionResult {
+    withdraw_energy_from(state, logistics::WITHDRAW_OFFERS)
+}
+
+/// Retreive energy from an offer of the logistics broker in the range of priorities
+/// # Contracts & Side effects
+/// Required the `loading` flag to be set to true
+/// If the creep is full sets the `loading` flag to false
+pub fn withdraw_energy_from<'a>(
+    state: &'a mut CreepState,
+    priorities: Range<i8>,
+) -> ExecutionResult {
     trace!("Getting energy");
 
     let target = {
-        if !state.creep_memory_bool(LOADING).unwrap_or(false) {
-            Err("not loading")?;
+        if !state.memory().loading {
+            Err(ExecutionError::WrongState)?;
         }
 
         if state.creep().carry_total() == state.creep().carry_capacity() {
-            state.creep_memory_set(LOADING.into(), false);
-            state.creep_memory_remove(TARGET);
-            Err("full")?;
+            let memory = state.memory_mut();
+            memory.loading = false;
+            memory.target = None;
+            Err(ExecutionError::Full)?;
         }
 
-        state
-            .creep_memory_string(TARGET)
-            .and_then(|id| get_object_erased(id))
-            .or_else(|| {
-                find_available_energy(state.creep()).map(|target| {
-                    let id = js! {
-                        return @{&target}.id;
-                    };
-                    let id: String = id.try_into().unwrap();
-
-                    state.creep_memory_set(TARGET.into(), id);
-                    target
-                })
-            })
-            .ok_or_else(|| {
-                state.creep_memory_remove(TARGET);
-                "Can't find energy source"
-            })?
+        let free = state.creep().carry_capacity() - state.creep().carry_total();
+        let id =
+            logistics::reserve(state, EntryKind::Offer, free, priorities).ok_or_else(|| {
+                state.memory_mut().target = None;
+                ExecutionError::NoTarget
+            })?;
+        get_object_erased(id.as_str()).ok_or_else(|| {
+            logistics::release(state);
+            ExecutionError::NoTarget
+        })?
     };
 
     let tasks = [
@@ -292,12 +326,15 @@ pub fn withdraw_energy<'a>(state: &'a mut CreepState) -> ExecutionResult {
         Task::new(|state: &mut WrappedState<RoomObject, CreepState>| {
             try_withdraw::<StructureContainer>(state.state, &state.item)
         }),
+        Task::new(|state: &mut WrappedState<RoomObject, CreepState>| {
+            await_handover(state.state, &state.item)
+        }),
     ];
     let mut state = WrappedState::new(target, state);
-    sequence(&mut state, tasks.iter()).map_err(|_| {
-        warn!("Got a target that can not be withdrawn from");
-        state.state.creep_memory_remove(TARGET);
-        "can't withdraw".into()
+    sequence(&mut state, tasks.iter()).map_err(|e| {
+        warn!("Got a target that can not be withdrawn from {}", e);
+        logistics::release(state.state);
+        e
     })
 }
 
@@ -305,46 +342,40 @@ fn try_withdraw<'a, T>(state: &mut CreepState, target: &'a RoomObject) -> Execut
 where
     T: 'a + Withdrawable + screeps::traits::TryFrom<&'a Reference>,
 {
-    let target = T::try_from(target.as_ref()).map_err(|_| "Failed to convert target")?;
-    withdraw(state, &target)
+    let target =
+        T::try_from(target.as_ref()).map_err(|_| ExecutionError::conversion("withdraw target"))?;
+    withdraw(state, &target, ResourceType::Energy)
+}
+
+/// Creeps can not be withdrawn from, wait next to the creep until it transfers its energy
+/// The offering creep completes the reservation, see `harvester::hand_over`
+fn await_handover(state: &mut CreepState, target: &RoomObject) -> ExecutionResult {
+    let target =
+        Creep::try_from(target.as_ref()).map_err(|_| ExecutionError::conversion("handover creep"))?;
+    if !state.creep().pos().is_near_to(&target) {
+        return move_to(state, &target);
+    }
+    traffic::pin(state);
+    Ok(Status::Running)
 }
 
-fn withdraw<'a, T>(state: &mut CreepState, target: &'a T) -> ExecutionResult
+/// Completes the reservation of the creep, if any, once withdrawn
+fn withdraw<'a, T>(state: &mut CreepState, target: &'a T, resource: ResourceType) -> ExecutionResult
 where
     T: Withdrawable,
 {
     let creep = state.creep();
     if creep.pos().is_near_to(target) {
-        let r = creep.withdraw_all(target, ResourceType::Energy);
+        let r = creep.withdraw_all(target, resource);
         if r != ReturnCode::Ok {
             debug!("couldn't withdraw: {:?}", r);
-            Err("couldn't withdraw")?;
+            Err(r)?;
         }
     } else {
-        move_to(creep, target)?;
+        return move_to(state, target);
     }
-    Ok(())
-}
-
-fn find_available_energy<'a>(creep: &'a Creep) -> Option<RoomObject> {
-    trace!("Finding new withdraw target");
-    let result = js! {
-        const creep = @{creep};
-        const ts = creep.pos.findClosestByRange(FIND_TOMBSTONES, {
-            filter: (ts) => ts.creep.my && ts.store[RESOURCE_ENERGY]
-        });
-        if (ts) {
-            return ts;
-        }
-        if (creep.room.storage && creep.room.storage.store[RESOURCE_ENERGY] > 0) {
-            return creep.room.storage;
-        }
-        const container = creep.pos.findClosestByRange(FIND_STRUCTURES, {
-            filter: (i) => i.structureType == STRUCTURE_CONTAINER && i.store[RESOURCE_ENERGY] > 0
-        });
-        return container;
-    };
-    result.try_into().unwrap_or_else(|_| None)
+    logistics::complete(state);
+    Ok(Status::Success)
 }
 
 /// Fallback harvest, method for a worker to harvest energy temporary
@@ -354,19 +385,19 @@ pub fn harvest(state: &mut CreepState) -> ExecutionResult {
     trace!("Worker harvesting");
 
     {
-        let loading = state.creep_memory_bool(LOADING);
-        if !loading.unwrap_or(false) {
-            Err("not loading")?;
+        if !state.memory().loading {
+            Err(ExecutionError::WrongState)?;
         }
         let creep = state.creep();
         if creep.carry_total() == creep.carry_capacity() {
-            state.creep_memory_set(LOADING.into(), false);
-            state.creep_memory_remove(TARGET);
-            return Ok(());
+            let memory = state.memory_mut();
+            memory.loading = false;
+            memory.target = None;
+            return Ok(Status::Success);
         }
     }
 
-    harvester::attempt_harvest(state, Some(TARGET))
+    harvester::attempt_harvest(state, |memory| &mut memory.target)
 }
 
 pub fn find_repair_target<'a>(room: &'a Room) -> Option<Structure> {
@@ -398,6 +429,9 @@ pub fn find_repair_target<'a>(room: &'a Room) -> Option<Structure> {
         .min_by_key(|s| s.as_attackable().map(|s| s.hits()).unwrap())
 }
 
+/// Ticks between the updates of the scout info of the room a creep passes through
+pub const SCOUT_INFO_INTERVAL: u32 = 10;
+
 pub fn update_scout_info(state: &mut CreepState) -> ExecutionResult {
     let creep = state.creep();
     let room = creep.room();
@@ -433,67 +467,53 @@ pub fn update_scout_info(state: &mut CreepState) -> ExecutionResult {
         time_of_recording: game::time(),
     };
 
-    unsafe {
-        (*state.mut_game_state())
-            .scout_intel
-            .insert(WorldPosition::from(room), info)
-    };
+    state
+        .mut_game_state()
+        .scout_intel
+        .insert(WorldPosition::from(room), info);
 
-    Ok(())
+    Ok(Status::Success)
 }
 
-/// target_key is a memory entry key
-pub fn approach_target_room(state: &mut CreepState, target_key: &str) -> ExecutionResult {
-    let target = state.creep_memory_string(target_key).ok_or("no target")?;
-
-    let creep = state.creep();
-
-    let room = creep.room();
-    let room_name = room.name();
-
-    if room_name == target {
-        Err("Already in the target room")?;
+/// `target_room` reads the name of the target room from the creep's memory
+pub fn approach_target_room(
+    state: &mut CreepState,
+    target_room: fn(&CreepMemory) -> Option<&str>,
+) -> ExecutionResult {
+    let target = target_room(state.memory())
+        .ok_or(ExecutionError::NoTarget)?
+        .to_owned();
+
+    if state.creep().room().name() == target {
+        Err(ExecutionError::WrongState)?;
     }
 
-    let result = js! {
-        const creep = @{creep};
-        const room = @{target};
-        const exitDir = creep.room.findExitTo(room);
-        const exit = creep.pos.findClosestByRange(exitDir);
-        return creep.moveTo(exit);
-    };
-
-    let result =
-        ReturnCode::try_from(result).map_err(|e| format!("Failed to parse return code {:?}", e))?;
-
-    match result {
-        ReturnCode::NoPath | ReturnCode::InvalidTarget => Err("Failed to move")?,
-        _ => Ok(()),
-    }
+    let center = WorldPosition::parse_name(&target)
+        .map_err(|e| format!("Got an invalid room name as target {:?}", e))?
+        .as_room_center();
+    move_to_range(state, &center, ROOM_RANGE)
 }
 
-pub fn sign_controller_stock_msgs(creep: &Creep) -> ExecutionResult {
+pub fn sign_controller_stock_msgs(state: &mut CreepState) -> ExecutionResult {
     const MESSAGES: &'static [&'static str] = &["Become as gods", "This cannot continue"];
     let msg = MESSAGES[game::time() as usize % MESSAGES.len()];
-    sign_controller(creep, msg)
+    sign_controller(state, msg)
 }
 
-pub fn sign_controller(creep: &Creep, msg: &str) -> ExecutionResult {
-    let controller = creep
-        .room()
-        .controller()
-        .ok_or_else(|| "Room has no controller")?;
+pub fn sign_controller(state: &mut CreepState, msg: &str) -> ExecutionResult {
+    let creep = state.creep();
+    let controller = creep.room().controller().ok_or(ExecutionError::NoTarget)?;
 
     if let Some(sign) = controller.sign() {
-        if sign.username == USERNAME {
-            Err("Already signed")?;
+        if sign.username == state.get_game_state().config.username {
+            Err(ExecutionError::WrongState)?;
         }
     }
 
     match creep.sign_controller(&controller, msg) {
-        ReturnCode::Ok => Ok(()),
-        ReturnCode::NotInRange => move_to(creep, &controller),
-        result => Err(format!("failed to sign controller {:?}", result))?,
+        ReturnCode::Ok => Ok(Status::Success),
+        ReturnCode::NotInRange => move_to(state, &controller),
+        result => Err(result)?,
     }
 }
 
@@ -0,0 +1,186 @@
+//! Move the creeps along the paths of `PathFinder.search`, cached in their memory
+//! A path is searched again when the destination changes or the creep is stuck or pushed off it
+//!
+use super::{traffic, CreepState};
+use crate::prelude::*;
+use screeps::{
+    constants::{find, Direction},
+    game,
+    objects::{Creep, OwnedStructureProperties, RoomPosition, Structure},
+    prelude::*,
+    ReturnCode,
+};
+use stdweb::unstable::TryInto;
+
+const PLAIN_COST: u8 = 2;
+const SWAMP_COST: u8 = 10;
+const ROAD_COST: u8 = 1;
+const BLOCKED: u8 = 255;
+/// Operations of a single search, `moveTo` uses 2000
+const MAX_OPS: u32 = 4000;
+
+/// Range to the center of a room close enough to be inside the room
+pub const ROOM_RANGE: u8 = 23;
+
+/// Move next to the target
+pub fn move_to<T>(state: &mut CreepState, target: &T) -> ExecutionResult
+where
+    T: HasPosition,
+{
+    move_to_range(state, target, 1)
+}
+
+/// Move into `range` of the target, one step of the cached path per tick
+pub fn move_to_range<T>(state: &mut CreepState, target: &T, range: u8) -> ExecutionResult
+where
+    T: HasPosition,
+{
+    let creep = state.creep().clone();
+    let target = target.pos();
+    if creep.pos().in_range_to(&target, range as u32) {
+        state.memory_mut().path = None;
+        return Ok(Status::Success);
+    }
+
+    let pos = creep.pos();
+    let here = (pos.x() as u8, pos.y() as u8);
+    let (room, x, y) = (target.room_name(), target.x() as u8, target.y() as u8);
+
+    let mut path = state
+        .memory_mut()
+        .path
+        .take()
+        .filter(|path| path.leads_to(&room, x, y, range));
+    if let Some(ref mut path) = path {
+        path.update(here, creep.fatigue() > 0);
+    }
+    let stuck = path.as_ref().map(|path| path.is_stuck()).unwrap_or(false);
+    let path = path.filter(|path| !stuck && path.next_direction().is_some());
+    let mut path = match path {
+        Some(path) => path,
+        None => search(&creep, &target, range, stuck).ok_or_else(|| {
+            debug!("No path to {} {} {}", room, x, y);
+            ReturnCode::NoPath
+        })?,
+    };
+
+    // The move is resolved with the moves of the other creeps by the traffic manager
+    if creep.fatigue() == 0 {
+        let direction = path
+            .next_direction()
+            .ok_or_else(|| ExecutionError::conversion("path direction"))?;
+        traffic::request_move(state, direction);
+        path.moved(here);
+    }
+    state.memory_mut().path = Some(path);
+    Ok(Status::Running)
+}
+
+/// Search a path into `range` of the target
+/// A stuck creep goes around the creeps of its room
+fn search(
+    creep: &Creep,
+    target: &RoomPosition,
+    range: u8,
+    avoid_creeps: bool,
+) -> Option<CachedPath> {
+    let room = creep.room().name();
+    let callback = move |room_name: String| -> Vec<u32> {
+        let avoid_creeps = avoid_creeps && room_name == room;
+        room_costs(&room_name, avoid_creeps)
+    };
+    let path = js! {
+        const callback = @{callback};
+        let result;
+        try {
+            result = PathFinder.search(@{creep.pos()}, {pos: @{target}, range: @{range as u32}}, {
+                plainCost: @{PLAIN_COST as u32},
+                swampCost: @{SWAMP_COST as u32},
+                maxOps: @{MAX_OPS},
+                roomCallback: (roomName) => {
+                    const matrix = new PathFinder.CostMatrix();
+                    for (const cost of callback(roomName)) {
+                        matrix.set(cost & 0xff, (cost >> 8) & 0xff, cost >> 16);
+                    }
+                    return matrix;
+                },
+            });
+        } finally {
+            // Freed even if the search throws, else the closure leaks
+            callback.drop();
+        }
+        return result.path.map((pos) => pos.x | (pos.y << 8));
+    };
+    let path: Vec<u32> = path
+        .try_into()
+        .map_err(|e| error!("Failed to convert path {:?}", e))
+        .ok()?;
+    let path = path
+        .into_iter()
+        .map(|pos| ((pos & 0xff) as u8, (pos >> 8) as u8))
+        .collect::<Vec<_>>();
+
+    let pos = creep.pos();
+    let path = CachedPath::new(
+        &target.room_name(),
+        target.x() as u8,
+        target.y() as u8,
+        range,
+        (pos.x() as u8, pos.y() as u8),
+        &path,
+    );
+    path.next_direction().map(|_| path)
+}
+
+/// Entries of the cost matrix of the room packed as `x | y << 8 | cost << 16`
+/// Rooms out of sight only use the terrain
+fn room_costs(room_name: &str, avoid_creeps: bool) -> Vec<u32> {
+    let room = match game::rooms::get(room_name) {
+        Some(room) => room,
+        None => return vec![],
+    };
+    let structures = room.find(find::STRUCTURES);
+    let roads = structures.iter().filter_map(|structure| match structure {
+        Structure::Road(road) => Some(pack_cost(&road.pos(), ROAD_COST)),
+        _ => None,
+    });
+    // Set after the roads, so a blocking structure built on a road blocks it
+    let blocked = structures
+        .iter()
+        .filter(|structure| !is_walkable(structure))
+        .map(|structure| pack_cost(&structure.pos(), BLOCKED));
+    let mut costs = roads.chain(blocked).collect::<Vec<_>>();
+
+    if avoid_creeps {
+        let creeps = room.find(find::CREEPS);
+        costs.extend(creeps.iter().map(|creep| pack_cost(&creep.pos(), BLOCKED)));
+    }
+    costs
+}
+
+fn is_walkable(structure: &Structure) -> bool {
+    match structure {
+        Structure::Road(_) | Structure::Container(_) => true,
+        Structure::Rampart(rampart) => rampart.my(),
+        _ => false,
+    }
+}
+
+fn pack_cost(pos: &RoomPosition, cost: u8) -> u32 {
+    pos.x() | pos.y() << 8 | (cost as u32) << 16
+}
+
+pub fn to_direction(direction: u8) -> Option<Direction> {
+    let result = match direction {
+        1 => Direction::Top,
+        2 => Direction::TopRight,
+        3 => Direction::Right,
+        4 => Direction::BottomRight,
+        5 => Direction::Bottom,
+        6 => Direction::BottomLeft,
+        7 => Direction::Left,
+        8 => Direction::TopLeft,
+        _ => None?,
+    };
+    Some(result)
+}
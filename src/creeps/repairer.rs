@@ -1,37 +1,38 @@
 //! Repair structures
 //!
-use super::{find_repair_target, move_to, CreepState, LOADING};
+use super::{find_repair_target, move_to_range, traffic, CreepState};
 use crate::prelude::*;
 use screeps::{
-    objects::{Creep, RoomObjectProperties, Structure},
+    objects::{RoomObjectProperties, Structure},
     ReturnCode,
 };
 
 pub fn attempt_repair<'a>(state: &mut CreepState) -> ExecutionResult {
-    let loading = state.creep_memory_bool(LOADING);
-    if loading.unwrap_or(false) {
-        return Err("loading".into());
+    if state.memory().loading {
+        return Err(ExecutionError::WrongState);
     }
     let creep = state.creep();
     if creep.carry_total() == 0 {
-        state.creep_memory_set("loading".into(), true);
-        Err("empty".into())
+        state.memory_mut().loading = true;
+        Err(ExecutionError::Empty)
     } else {
         let target = find_repair_target(&creep.room()).ok_or_else(|| {
-            let error = format!("Could not find a repair target");
-            debug!("{}", error);
-            error
+            debug!("Could not find a repair target");
+            ExecutionError::NoTarget
         })?;
-        repair(creep, &target)
+        repair(state, &target)
     }
 }
 
-fn repair<'a>(creep: &'a Creep, target: &'a Structure) -> ExecutionResult {
-    let res = creep.repair(target);
+fn repair<'a>(state: &mut CreepState, target: &'a Structure) -> ExecutionResult {
+    let res = state.creep().repair(target);
     match res {
-        ReturnCode::Ok => Ok(()),
-        ReturnCode::NotInRange => move_to(creep, target),
-        _ => Err(format!("Unexpected ReturnCode {:?}", res))?,
+        ReturnCode::Ok => {
+            traffic::pin(state);
+            Ok(Status::Running)
+        }
+        ReturnCode::NotInRange => move_to_range(state, target, 3),
+        _ => Err(res)?,
     }
 }
 
@@ -1,9 +1,9 @@
 pub use super::spawn_info::*;
-use super::{conqueror, defender, gofer, harvester, lrh, lrw, scout, upgrader, worker};
+use super::{conqueror, defender, gofer, harvester, lrh, lrw, miner, scout, upgrader, worker};
 use crate::prelude::*;
 use arrayvec::ArrayVec;
-use screeps::objects::Room;
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, Eq, PartialEq, Ord, PartialOrd)]
 #[repr(u8)]
@@ -18,6 +18,7 @@ pub enum Role {
     Lrw = 7,
     Scout = 8,
     Defender = 9,
+    Miner = 10,
 }
 
 impl From<u8> for Role {
@@ -33,6 +34,7 @@ impl From<u8> for Role {
             7 => Role::Lrw,
             8 => Role::Scout,
             9 => Role::Defender,
+            10 => Role::Miner,
             _ => unimplemented!("Role {} is not unimplemented!", item),
         }
     }
@@ -51,53 +53,49 @@ impl Display for Role {
             Role::Conqueror => "Conqueror",
             Role::Scout => "Scout",
             Role::Defender => "Defender",
+            Role::Miner => "Miner",
         };
         write!(f, "{}", name)
     }
 }
 
-type RoleArray = [Role; 9];
+/// Parse the name of the role, case insensitive
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        Role::all_roles()
+            .into_iter()
+            .find(|role| role.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown role {:?}", name))
+    }
+}
+
+type RoleArray = [Role; 10];
 impl Role {
     pub fn all_roles() -> ArrayVec<RoleArray> {
         use self::Role::*;
         const ROLES: RoleArray = [
-            Upgrader, Harvester, Worker, Gofer, Lrh, Conqueror, Lrw, Scout, Defender,
+            Upgrader, Harvester, Worker, Gofer, Lrh, Conqueror, Lrw, Scout, Defender, Miner,
         ];
         ROLES
             .iter()
             // Trigger compilation error on a new role if it's missing
             .filter_map(|r| match r {
                 Scout | Upgrader | Harvester | Worker | Gofer | Lrh | Conqueror | Lrw
-                | Defender => Some(*r),
+                | Defender | Miner => Some(*r),
                 Unknown => None,
             })
             .collect()
     }
-}
 
-// TODO: return an array of all roles to spawn in order of priority
-/// Get the next target role in the given room
-pub fn next_role<'a>(state: &'a mut GameState, room: &'a Room) -> Option<Role> {
-    let creeps = { state.count_creeps_in_room(room).clone() };
-    creeps
-        .into_iter()
-        .fold(None, |result: Option<Role>, (role, actual)| {
-            let expected = target_number_of_role_in_room(role, room, state);
-            if expected <= actual {
-                return result;
-            }
-            result
-                .map(|result| {
-                    let result_prio = role_priority(room, result);
-                    let role_prio = role_priority(room, role);
-                    if role_prio > result_prio {
-                        role
-                    } else {
-                        result
-                    }
-                })
-                .or_else(|| Some(role))
-        })
+    /// Creeps keeping the economy and the defence running are executed first
+    pub fn is_critical(self) -> bool {
+        match self {
+            Role::Harvester | Role::Miner | Role::Gofer | Role::Defender => true,
+            _ => false,
+        }
+    }
 }
 
 /// Run the creep according to the given role
@@ -112,12 +110,17 @@ pub fn run_role<'a>(state: &mut CreepState, role: Role) -> ExecutionResult {
         Role::Lrw => lrw::run(state),
         Role::Scout => scout::run(state),
         Role::Defender => defender::run(state),
+        Role::Miner => miner::run(state),
         _ => unimplemented!(),
     };
 
     result.map_err(|e| {
-        warn!("Creep {} is idle: {}", state.creep_name().0, e);
-        ExecutionError::from("Idle")
+        if e.is_expected() {
+            debug!("Creep {} is idle: {}", state.creep_name().0, e);
+        } else {
+            warn!("Creep {} is idle: {}", state.creep_name().0, e);
+        }
+        e
     })
 }
 
@@ -1,33 +1,43 @@
-use super::{approach_target_room, update_scout_info, CreepState, TARGET};
+use super::{approach_target_room, update_scout_info, CreepState};
 use crate::prelude::*;
 use crate::rooms::neighbours;
 use screeps::prelude::*;
 
 pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
-    let tasks = [
-        Task::new(|state| {
-            update_scout_info(state).unwrap_or_else(|e| {
-                warn!("Failed to update scout info {}", e);
-            });
-            state.creep().say("🛰️", false);
-            Err("Continue")?
-        }),
-        Task::new(|state| {
-            approach_target_room(state, TARGET).map_err(|e| {
-                state.creep_memory_remove(TARGET);
-                debug!("Approach failed {}", e);
-                e
+    Parallel::new()
+        .with_name("Scout")
+        .with_child(
+            Task::new(|state: &mut CreepState| {
+                update_scout_info(state).unwrap_or_else(|e| {
+                    warn!("Failed to update scout info {}", e);
+                    Status::Success
+                });
+                state.creep().say("🛰️", false);
+                Ok(Status::Success)
             })
-        }),
-        Task::new(|state| set_next_room(state)),
-    ];
-
-    sequence(state, tasks.iter())
+            .with_name("Update scout info"),
+        )
+        .with_child(
+            Sequence::new()
+                .with_name("Explore")
+                .with_child(
+                    Task::new(|state: &mut CreepState| {
+                        approach_target_room(state, CreepMemory::target).map_err(|e| {
+                            state.memory_mut().target = None;
+                            debug!("Approach failed {}", e);
+                            e
+                        })
+                    })
+                    .with_name("Approach target room"),
+                )
+                .with_child(Task::new(|state| set_next_room(state)).with_name("Set next room")),
+        )
+        .tick(state)
 }
 
 fn set_next_room(state: &mut CreepState) -> ExecutionResult {
-    if state.creep_memory_string(TARGET).is_some() {
-        Err("Already has a target")?;
+    if state.memory().target.is_some() {
+        Err(ExecutionError::WrongState)?;
     }
 
     let room = state.creep().room();
@@ -47,7 +57,7 @@ fn set_next_room(state: &mut CreepState) -> ExecutionResult {
         }
     }
 
-    state.creep_memory_set(TARGET, target_room.to_string().as_str());
-    Ok(())
+    state.memory_mut().target = Some(target_room.to_string().as_str().into());
+    Ok(Status::Success)
 }
 
@@ -1,13 +1,10 @@
+use super::miner::work_parts_to_drain;
 use super::roles::Role;
 use crate::prelude::WorldPosition;
-use crate::state::GameState;
+use crate::state::{GameState, RoomState, ThreatLevel};
 use arrayvec::ArrayVec;
-use screeps::{
-    constants::find,
-    objects::{HasStore, Room, StructureContainer},
-    Part,
-};
-use stdweb::unstable::TryInto;
+use screeps::{constants::find, objects::Room, Part};
+use std::collections::BTreeMap;
 
 pub type BodyCollection = ArrayVec<[Part; 16]>;
 
@@ -21,7 +18,7 @@ pub struct SpawnConfig {
 pub fn role_priority<'a>(_room: &'a Room, role: Role) -> i8 {
     match role {
         Role::Defender => 4,
-        Role::Harvester => 3,
+        Role::Harvester | Role::Miner => 3,
         Role::Gofer => 2,
         Role::Worker => 1,
         Role::Scout => -1,
@@ -32,56 +29,71 @@ pub fn role_priority<'a>(_room: &'a Room, role: Role) -> i8 {
     }
 }
 
-/// Max number of creeps of a given role in the given room
-pub fn target_number_of_role_in_room<'a>(role: Role, room: &'a Room, game_state: &GameState) -> i8 {
-    let level = room.controller().map(|l| l.level()).unwrap_or(0);
-    let room_pos = WorldPosition::from(room);
+/// Number of creeps of each role the room should have
+pub fn role_quotas(
+    room_state: &RoomState,
+    room_pos: WorldPosition,
+    game_state: &GameState,
+) -> BTreeMap<Role, i8> {
     let n_flags = game_state
         .expansion
         .iter()
         .filter(|w| w.dist(room_pos) <= 10)
         .count()
         .min(255) as i8;
-    let n_sources = room.find(find::SOURCES).len() as i8;
-    let containers = js! {
-        const room = @{room};
-        return room.find(FIND_STRUCTURES, {
-            filter: (s) => s.structureType == STRUCTURE_CONTAINER
-        });
-    };
-    let containers: Vec<StructureContainer> = containers.try_into().unwrap();
-    let energy_in_containers = containers.iter().map(|c| c.energy()).sum::<u32>();
-    let n_containers = containers.len() as i8;
-    let n_constructions = (room.find(find::CONSTRUCTION_SITES).len()) as i8;
-    const UPGRADER_COUNT: i8 = 1;
-    match role {
-        Role::Defender => room.find(find::HOSTILE_CREEPS).len().min(1) as i8,
-        Role::Upgrader => n_containers.min(UPGRADER_COUNT),
-        Role::Harvester => n_sources,
-        Role::Worker => {
-            let mut target_workers = n_constructions.min(2);
-            if n_containers > 0 {
-                if energy_in_containers > 1000 {
-                    target_workers += 3;
+    let n_attack_targets = game_state
+        .attack_targets
+        .iter()
+        .filter(|w| w.dist(room_pos) <= 3)
+        .count()
+        .min(2) as i8;
+    let upgrader_count = game_state.config.upgrader_count;
+    let level = room_state.level;
+    let n_sources = room_state.n_sources;
+    let n_containers = room_state.n_containers;
+    Role::all_roles()
+        .into_iter()
+        .map(|role| {
+            let quota = match role {
+                Role::Defender => {
+                    let n_defenders = match room_state.threat {
+                        ThreatLevel::None => 0,
+                        ThreatLevel::Low => 1,
+                        ThreatLevel::High => 2,
+                    };
+                    n_defenders.max(n_attack_targets)
                 }
-                target_workers += UPGRADER_COUNT
-            }
+                Role::Upgrader => n_containers.min(upgrader_count),
+                // Sources with a container are left to the miners
+                Role::Harvester => n_sources - room_state.n_source_containers,
+                Role::Miner => room_state.n_source_containers,
+                Role::Worker => {
+                    let mut target_workers = room_state.n_construction_sites.min(2);
+                    if n_containers > 0 {
+                        if room_state.energy_in_containers > 1000 {
+                            target_workers += 3;
+                        }
+                        target_workers += upgrader_count
+                    }
 
-            target_workers
-        }
-        Role::Conqueror => n_flags.max(1),
-        Role::Lrh => {
-            if n_containers == 0 {
-                0
-            } else {
-                (level * 2).min(4) as i8
-            }
-        }
-        Role::Gofer => n_sources.min(n_containers as i8),
-        Role::Lrw => n_flags.max(1),
-        Role::Scout => 1,
-        Role::Unknown => 0,
-    }
+                    target_workers
+                }
+                Role::Conqueror => n_flags.max(1),
+                Role::Lrh => {
+                    if n_containers == 0 {
+                        0
+                    } else {
+                        (level * 2).min(4) as i8
+                    }
+                }
+                Role::Gofer => n_sources.min(n_containers),
+                Role::Lrw => n_flags.max(1),
+                Role::Scout => 1,
+                Role::Unknown => 0,
+            };
+            (role, quota)
+        })
+        .collect()
 }
 
 pub fn spawn_config_by_role(room: &Room, role: Role) -> SpawnConfig {
@@ -103,6 +115,7 @@ fn basic_role_parts<'a>(_room: &Room, role: Role) -> BodyCollection {
         Role::Lrw => [Part::Move, Part::Move, Part::Carry, Part::Work].iter(),
         Role::Scout => [Part::Move].iter(),
         Role::Defender => [Part::Move, Part::Attack].iter(),
+        Role::Miner => [Part::Move, Part::Work].iter(),
         Role::Unknown => [].iter(),
     };
     it.map(|x| *x).collect()
@@ -111,7 +124,7 @@ fn basic_role_parts<'a>(_room: &Room, role: Role) -> BodyCollection {
 /// Intended parts to be appended to 'role_parts'
 fn role_part_scale<'a>(_room: &Room, role: Role) -> BodyCollection {
     let it = match role {
-        Role::Harvester => [Part::Work].iter(),
+        Role::Harvester | Role::Miner => [Part::Work].iter(),
         Role::Scout | Role::Conqueror => [].iter(),
         Role::Gofer => [Part::Move, Part::Carry, Part::Carry].iter(),
         Role::Lrh => [Part::Move, Part::Carry, Part::Work, Part::Move].iter(),
@@ -137,6 +150,16 @@ fn role_part_max(room: &Room, role: Role) -> Option<usize> {
 
     let result = match role {
         Role::Harvester => Some(8),
+        Role::Miner => {
+            let energy_capacity = room
+                .find(find::SOURCES)
+                .iter()
+                .map(|source| source.energy_capacity())
+                .max()
+                .unwrap_or(0);
+            // A single MOVE, the miner never moves after reaching its container
+            Some(1 + work_parts_to_drain(energy_capacity) as usize)
+        }
         Role::Lrw | Role::Lrh | Role::Worker | Role::Upgrader => Some(worker_count),
         Role::Conqueror => None,
         Role::Scout => None,
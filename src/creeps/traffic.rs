@@ -0,0 +1,93 @@
+//! Submit the moves of the creeps as intents and move them once all the creeps ran
+//! See `state::traffic` for the resolution of the intents
+//!
+use super::pathing::to_direction;
+use super::CreepState;
+use crate::prelude::*;
+use screeps::{constants::find, game, prelude::*, ReturnCode, RoomPosition};
+use stdweb::unstable::TryFrom;
+
+/// Ask to move the creep in the direction at the end of the tick
+/// Critical creeps get the contested tiles first
+pub fn request_move(state: &mut CreepState, direction: u8) {
+    let creep = state.creep().clone();
+    let priority = state
+        .memory()
+        .role
+        .map(|role| role.is_critical() as i8)
+        .unwrap_or(0);
+    state
+        .mut_game_state()
+        .traffic(WorldPosition::from(creep.room()))
+        .request(&creep.name(), tile(&creep.pos()), direction, priority);
+}
+
+/// Keep the creep on its tile while it works in place, e.g. harvesting or upgrading
+/// The creeps moving past it go around instead of shoving it
+pub fn pin(state: &mut CreepState) {
+    let room = state.current_room();
+    let name = state.creep_name().0.to_owned();
+    state.mut_game_state().traffic(room).pin(&name);
+}
+
+/// Move the creeps as resolved by the traffic managers of their rooms
+/// Shoved creeps drop their path, blocked ones search a path around the creeps in the next tick
+pub fn run(state: &mut GameState) {
+    for (room, mut traffic) in state.take_traffic() {
+        let room = match game::rooms::get(room.to_string().as_str()) {
+            Some(room) => room,
+            None => continue,
+        };
+        for creep in room.find(find::MY_CREEPS) {
+            if creep.spawning() {
+                continue;
+            }
+            traffic.occupy(&creep.name(), tile(&creep.pos()), false);
+        }
+        for creep in room.find(find::HOSTILE_CREEPS) {
+            traffic.occupy(&creep.id(), tile(&creep.pos()), true);
+        }
+
+        let room_name = room.name();
+        let resolution = traffic.resolve(|tile| is_walkable(&room_name, tile));
+
+        for (name, direction) in resolution.moves.iter() {
+            let creep = game::creeps::get(name);
+            if let (Some(creep), Some(direction)) = (creep, to_direction(*direction)) {
+                let res = creep.move_direction(direction);
+                if res != ReturnCode::Ok && res != ReturnCode::Tired {
+                    debug!("Move of {} failed {:?}", name, res);
+                }
+            }
+        }
+        for name in resolution.shoved.iter() {
+            state.creep_memory_entry(CreepName(name)).path = None;
+        }
+        for name in resolution.blocked.iter() {
+            if let Some(path) = state.creep_memory_entry(CreepName(name)).path.as_mut() {
+                path.stuck = STUCK_LIMIT;
+            }
+        }
+    }
+}
+
+fn tile(pos: &RoomPosition) -> (u8, u8) {
+    (pos.x() as u8, pos.y() as u8)
+}
+
+/// Tiles without walls and obstacles the idle creeps can be shoved to
+fn is_walkable(room: &str, (x, y): (u8, u8)) -> bool {
+    let walkable = js! {
+        const room = @{room};
+        const x = @{x as u32};
+        const y = @{y as u32};
+        if (Game.map.getRoomTerrain(room).get(x, y) & TERRAIN_MASK_WALL) {
+            return false;
+        }
+        return !new RoomPosition(x, y, room).lookFor(LOOK_STRUCTURES).some((s) =>
+            OBSTACLE_OBJECT_TYPES.includes(s.structureType)
+                || (s.structureType == STRUCTURE_RAMPART && !s.my && !s.isPublic)
+        );
+    };
+    bool::try_from(walkable).unwrap_or(false)
+}
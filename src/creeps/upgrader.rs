@@ -1,48 +1,44 @@
 //! Upgrade Controllers
 //!
-use super::{move_to, sign_controller_stock_msgs, withdraw_energy, CreepState, LOADING};
+use super::{move_to_range, sign_controller_stock_msgs, traffic, withdraw_energy, CreepState};
 use crate::prelude::*;
 use screeps::{prelude::*, ReturnCode};
 
 pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
-    let tasks = [
-        Task::new(|state| {
-            let tasks = [
-                Task::new(|state| attempt_upgrade(state)),
-                Task::new(|state: &mut CreepState| sign_controller_stock_msgs(state.creep())),
-            ];
-
-            selector(state, tasks.iter())
-        })
-        .with_name("Attempt upgrade"),
-        Task::new(|state| withdraw_energy(state)).with_name("Withdraw energy"),
-        Task::new(|state| attempt_upgrade(state)).with_name("Attempt upgrade"),
-    ];
-
-    sequence(state, tasks.iter())
+    Sequence::new()
+        .with_name("Upgrader")
+        // Fails once the controller carries our sign
+        .with_child(
+            Task::new(|state| sign_controller_stock_msgs(state)).with_name("Sign controller"),
+        )
+        .with_child(Task::new(|state| attempt_upgrade(state)).with_name("Upgrade"))
+        .with_child(Task::new(|state| withdraw_energy(state)).with_name("Withdraw energy"))
+        .with_child(Task::new(|state| attempt_upgrade(state)).with_name("Attempt upgrade"))
+        .tick(state)
 }
 
 pub fn attempt_upgrade<'a>(state: &mut CreepState) -> ExecutionResult {
-    let loading = state.creep_memory_bool(LOADING);
-    if loading.unwrap_or(false) {
-        return Err("loading")?;
+    if state.memory().loading {
+        return Err(ExecutionError::WrongState);
     }
     if state.creep().carry_total() == 0 {
-        state.creep_memory_set("loading".into(), true);
-        Err("empty")?;
+        state.memory_mut().loading = true;
+        Err(ExecutionError::Empty)?;
     }
     let controller = state.creep().room().controller().ok_or_else(|| {
-        let error = "Creep has no access to a controller in the room!";
-        error!("{}", error);
-        error
+        error!("Creep has no access to a controller in the room!");
+        ExecutionError::NoTarget
     })?;
     let res = state.creep().upgrade_controller(&controller);
     match res {
-        ReturnCode::Ok => Ok(()),
-        ReturnCode::NotInRange => move_to(state.creep(), &controller),
+        ReturnCode::Ok => {
+            traffic::pin(state);
+            Ok(Status::Running)
+        }
+        ReturnCode::NotInRange => move_to_range(state, &controller, 3),
         _ => {
             error!("Failed to upgrade controller {:?}", res);
-            Err("Failed to upgrade controller")?
+            Err(res)?
         }
     }
 }
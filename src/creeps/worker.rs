@@ -1,92 +1,117 @@
 //! Build structures
 //!
 use super::{
-    harvest, move_to, repairer, upgrader, withdraw_energy, CreepState, LOADING, TARGET, TASK,
+    harvest, move_to, move_to_range, repairer, traffic, upgrader, withdraw_energy, CreepState,
 };
+use crate::flags::{self, FlagCommand};
 use crate::prelude::*;
-use num::FromPrimitive;
 use screeps::{
     constants::find, game::get_object_typed, objects::ConstructionSite, prelude::*, ReturnCode,
 };
+use stdweb::unstable::TryFrom;
 
-#[derive(Debug, Clone, Copy, FromPrimitive, ToPrimitive)]
-#[repr(u8)]
-enum WorkerState {
-    Idle = 0,
-    Building = 1,
-    PickingUpEnergy = 2,
-    WithdrawingEnergy = 3,
-    Harvesting = 4,
-    Repairing = 5,
-}
+/// Ticks a worker keeps trying to build its target, so unreachable sites are given up
+const BUILD_TIMEOUT: u32 = 100;
 
 pub fn run<'a>(state: &mut CreepState) -> ExecutionResult {
-    let last_task = state.creep_memory_i64(TASK).unwrap_or(0);
-    let last_task: WorkerState =
-        WorkerState::from_u32(last_task as u32).unwrap_or(WorkerState::Idle);
-
-    let mut priorities = [0; 6];
-    priorities[last_task as usize] += 1;
-
-    let mut tasks = [
-        Task::new(|state| attempt_build(state))
-            .with_name("Attempt build")
-            .with_priority(priorities[WorkerState::Building as usize])
-            .with_state_save(WorkerState::Building),
-        Task::new(|state: &mut CreepState| withdraw_energy(state))
-            .with_name("Withdraw energy")
-            .with_state_save(WorkerState::WithdrawingEnergy)
-            .with_priority(priorities[WorkerState::WithdrawingEnergy as usize]),
-        Task::new(|state| harvest(state))
-            .with_name("Harvest")
-            .with_state_save(WorkerState::Harvesting)
-            .with_priority(priorities[WorkerState::Harvesting as usize]),
+    Sequence::new()
+        .with_name("Worker")
+        .with_child(Task::new(|state| attempt_dismantle(state)).with_name("Attempt dismantle"))
+        // The target is dropped once building it fails or times out
+        .with_child(
+            Sequence::new()
+                .with_name("Build")
+                .with_child(Timeout::new(
+                    BUILD_TIMEOUT,
+                    Task::new(|state| attempt_build(state)).with_name("Attempt build"),
+                ))
+                .with_child(AlwaysFail::new(
+                    Task::new(|state| delete_target(state)).with_name("Delete target"),
+                )),
+        )
+        .with_child(
+            Task::new(|state: &mut CreepState| withdraw_energy(state)).with_name("Withdraw energy"),
+        )
+        .with_child(Task::new(|state| harvest(state)).with_name("Harvest"))
         // If nothing can be built
-        Task::new(|state: &mut CreepState| repairer::attempt_repair(state))
-            .with_required_bucket(500)
-            .with_priority(priorities[WorkerState::Repairing as usize])
-            .with_state_save(WorkerState::Repairing)
-            .with_name("Attempt repair"),
-        Task::new(|state: &mut CreepState| {
-            state.creep_memory_remove(TARGET);
-            Err("continue")?
-        })
-        .with_name("Delete target"),
-        Task::new(|state| upgrader::attempt_upgrade(state)).with_name("Attempt upgrade"),
-    ];
+        .with_child(
+            Task::new(|state: &mut CreepState| repairer::attempt_repair(state))
+                .with_required_bucket(500)
+                .with_name("Attempt repair"),
+        )
+        .with_child(AlwaysFail::new(
+            Task::new(|state| delete_target(state)).with_name("Delete target"),
+        ))
+        .with_child(
+            Task::new(|state| upgrader::attempt_upgrade(state)).with_name("Attempt upgrade"),
+        )
+        .tick(state)
+}
 
-    sorted_by_priority(&mut tasks);
-    sequence(state, tasks.iter())
+fn delete_target(state: &mut CreepState) -> ExecutionResult {
+    state.memory_mut().target = None;
+    Ok(Status::Success)
+}
+
+/// Dismantle the structure under a dismantle flag in the room
+fn attempt_dismantle<'a>(state: &mut CreepState) -> ExecutionResult {
+    let creep = state.creep();
+    let flag = flags::find_in_room(&creep.room(), FlagCommand::Dismantle)
+        .into_iter()
+        .next()
+        .ok_or(ExecutionError::NoTarget)?;
+    let result = js! {
+        const creep = @{creep};
+        const structures = @{&flag}.pos.lookFor(LOOK_STRUCTURES);
+        if (!structures.length) {
+            return ERR_INVALID_TARGET;
+        }
+        return creep.dismantle(structures[0]);
+    };
+    let result = ReturnCode::try_from(result)
+        .map_err(|e| ExecutionError::conversion(format!("dismantle result {:?}", e)))?;
+    match result {
+        ReturnCode::Ok => {
+            traffic::pin(state);
+            Ok(Status::Running)
+        }
+        ReturnCode::NotInRange => move_to(state, &flag),
+        ReturnCode::InvalidTarget => Err(ExecutionError::NoTarget)?,
+        _ => Err(result)?,
+    }
 }
 
 pub fn attempt_build<'a>(state: &mut CreepState) -> ExecutionResult {
     trace!("Building");
 
-    let loading = state.creep_memory_bool(LOADING);
-    if loading.unwrap_or(false) {
-        Err("loading")?;
+    if state.memory().loading {
+        Err(ExecutionError::WrongState)?;
     }
 
     if state.creep().carry_total() == 0 {
-        state.creep_memory_set(LOADING.into(), true);
-        Err("empty")?
+        state.memory_mut().loading = true;
+        Err(ExecutionError::Empty)?
     }
-    let target = get_build_target(state).ok_or_else(|| format!("Failed to find build target"))?;
+    let target = get_build_target(state).ok_or(ExecutionError::NoTarget)?;
     let res = state.creep().build(&target);
     match res {
-        ReturnCode::Ok => Ok(()),
-        ReturnCode::NotInRange => move_to(state.creep(), &target),
+        ReturnCode::Ok => {
+            traffic::pin(state);
+            Ok(Status::Running)
+        }
+        ReturnCode::NotInRange => move_to_range(state, &target, 3),
         _ => {
             error!("Failed to build target {:?} {:?}", res, target.id());
-            state.creep_memory_remove(TARGET);
-            Err("Failed to build target")?
+            state.memory_mut().target = None;
+            Err(res)?
         }
     }
 }
 
 fn get_build_target<'a>(state: &mut CreepState) -> Option<ConstructionSite> {
     state
-        .creep_memory_string(TARGET)
+        .memory()
+        .target()
         .and_then(|id| get_object_typed(id).unwrap_or(None))
         .or_else(|| {
             let sites = state.creep().room().find(find::MY_CONSTRUCTION_SITES);
@@ -95,7 +120,7 @@ fn get_build_target<'a>(state: &mut CreepState) -> Option<ConstructionSite> {
                 .min_by_key(|s| s.progress_total() - s.progress())
                 .ok_or_else(|| debug!("Could not find a build target"))
                 .map(|site| {
-                    state.creep_memory_set(TARGET.into(), site.id());
+                    state.memory_mut().target = Some(site.id());
                     site
                 })
                 .ok()
@@ -0,0 +1,26 @@
+//! Request defenders for the rooms under attack or next to an attack target
+//!
+use crate::creeps::Role;
+use crate::prelude::*;
+use crate::structures::spawns::request_missing;
+use screeps::constants::find;
+use screeps::game;
+
+/// Defenders are needed now or not at all
+const DEFENDER_REQUEST_TTL: u32 = 20;
+
+pub fn run<'a>(state: &mut GameState) -> ExecutionResult {
+    for room in game::rooms::values() {
+        if room.find(find::MY_SPAWNS).is_empty() {
+            continue;
+        }
+        request_missing(
+            state,
+            &room,
+            Role::Defender,
+            "defense",
+            DEFENDER_REQUEST_TTL,
+        );
+    }
+    Ok(Status::Success)
+}
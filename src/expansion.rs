@@ -1,13 +1,15 @@
 //! Flag rooms as targets for expansion based on scout data
 //!
+use crate::creeps::Role;
 use crate::prelude::*;
+use crate::structures::spawns::request_missing;
 use screeps::constants::find;
 use screeps::game;
 use screeps::objects::OwnedStructureProperties;
 use std::collections::{HashMap, BTreeSet};
 
 pub fn run<'a>(state: &'a mut GameState) -> ExecutionResult {
-    let tasks = [Task::new(remove_exp_markers)];
+    let tasks = [Task::new(remove_exp_markers), Task::new(request_claimers)];
     selector(state, tasks.iter())
 }
 
@@ -44,6 +46,21 @@ fn remove_exp_markers(state: &mut GameState) -> ExecutionResult {
 
     state.expansion = retain;
 
-    Ok(())
+    Ok(Status::Success)
 }
 
+/// Conquerors are not urgent, the requests are kept until the spawns get to them
+const CLAIMER_REQUEST_TTL: u32 = 500;
+
+/// Request the conquerors and the long range workers of the expansions
+fn request_claimers(state: &mut GameState) -> ExecutionResult {
+    for room in game::rooms::values() {
+        if room.find(find::MY_SPAWNS).is_empty() {
+            continue;
+        }
+        for role in [Role::Conqueror, Role::Lrw].iter() {
+            request_missing(state, &room, *role, "expansion", CLAIMER_REQUEST_TTL);
+        }
+    }
+    Ok(Status::Success)
+}
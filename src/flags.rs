@@ -1,14 +1,236 @@
+//! Manual overrides placed as flags
+//! The command of a flag is given by the prefix of its name or else by its primary colour
+//! The prefix is the whole name or followed by a delimiter, e.g. `mine`, `mine:W1N1` or `mine_2`
+//! Flags are removed once their command is complete
+//!
 use crate::prelude::*;
-use screeps::game::flags;
+use screeps::constants::{find, Color};
+use screeps::game::{self, flags};
+use screeps::objects::{Flag, OwnedStructureProperties, Room};
 use screeps::HasPosition;
+use stdweb::unstable::TryFrom;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagCommand {
+    /// Claim the room, `expand` or white and purple flags
+    Expand,
+    /// Clear the room of hostiles, `attack` or red flags
+    Attack,
+    /// Send long range harvesters to the room, `mine` or yellow flags
+    RemoteMine,
+    /// Keep the room planner off the tile, `block` or grey flags
+    Block,
+    /// Idle defenders wait here, `rally` or green flags
+    Rally,
+    /// Workers dismantle the structure under the flag, `dismantle` or orange flags
+    Dismantle,
+    /// Build the storage on the tile, `storage` or blue flags
+    Storage,
+}
+
+/// Cyan and brown flags are left for notes
+const COMMANDS_BY_COLOR: &[(Color, FlagCommand)] = &[
+    (Color::White, FlagCommand::Expand),
+    (Color::Purple, FlagCommand::Expand),
+    (Color::Red, FlagCommand::Attack),
+    (Color::Yellow, FlagCommand::RemoteMine),
+    (Color::Grey, FlagCommand::Block),
+    (Color::Green, FlagCommand::Rally),
+    (Color::Orange, FlagCommand::Dismantle),
+    (Color::Blue, FlagCommand::Storage),
+];
+
+const COMMANDS_BY_PREFIX: &[(&str, FlagCommand)] = &[
+    ("expand", FlagCommand::Expand),
+    ("attack", FlagCommand::Attack),
+    ("mine", FlagCommand::RemoteMine),
+    ("block", FlagCommand::Block),
+    ("rally", FlagCommand::Rally),
+    ("dismantle", FlagCommand::Dismantle),
+    ("storage", FlagCommand::Storage),
+];
+
+impl FlagCommand {
+    pub fn of(flag: &Flag) -> Option<Self> {
+        Self::of_name(&flag.name()).or_else(|| {
+            let color = flag.color();
+            COMMANDS_BY_COLOR
+                .iter()
+                .find(|(c, _)| *c == color)
+                .map(|(_, command)| *command)
+        })
+    }
+
+    /// Command of the prefix of the name, names merely starting with a prefix are not commands
+    fn of_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        COMMANDS_BY_PREFIX
+            .iter()
+            .find(|(prefix, _)| {
+                name.starts_with(prefix)
+                    && name[prefix.len()..]
+                        .chars()
+                        .next()
+                        .map(|c| !c.is_alphanumeric())
+                        .unwrap_or(true)
+            })
+            .map(|(_, command)| *command)
+    }
+}
+
+/// Flags of the command in the room
+pub fn find_in_room(room: &Room, command: FlagCommand) -> Vec<Flag> {
+    room.find(find::FLAGS)
+        .into_iter()
+        .filter(|flag| FlagCommand::of(flag) == Some(command))
+        .collect()
+}
+
+/// Handlers return Success once the command is complete
+/// Rooms of the flags are rebuilt on every run, so removed flags are forgotten
 pub fn run<'a>(state: &mut GameState) -> ExecutionResult {
-    let flags = flags::values();
-    flags.into_iter().for_each(move |flag| {
-        let room = WorldPosition::parse_name(&flag.pos().room_name()).unwrap();
-        flag.remove();
-        state.expansion.insert(room);
-    });
-    Ok(())
+    state.attack_targets.clear();
+    state.remote_mining.clear();
+
+    let flags = flags::values()
+        .into_iter()
+        .filter_map(|flag| FlagCommand::of(&flag).map(|command| (flag, command)))
+        .collect::<Vec<_>>();
+    for ((flag, command), room) in in_rooms(flags, |(flag, _)| flag.pos().room_name()) {
+        let result = match command {
+            FlagCommand::Expand => expand(state, &flag, room),
+            FlagCommand::Attack => attack(state, &flag, room),
+            FlagCommand::RemoteMine => remote_mine(state, &flag, room),
+            FlagCommand::Dismantle => dismantle(state, &flag, room),
+            FlagCommand::Storage => storage(state, &flag, room),
+            // Read by the creeps and the room planner
+            FlagCommand::Block | FlagCommand::Rally => Ok(Status::Running),
+        };
+        match result {
+            Ok(Status::Success) => {
+                info!("Flag {} {:?} is complete", flag.name(), command);
+                flag.remove();
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Flag {} {:?} failed {}", flag.name(), command, e),
+        }
+    }
+    Ok(Status::Success)
 }
 
+/// Pair the items with the rooms they are in
+/// Items in a room with a malformed name are skipped, so they do not stop the others
+fn in_rooms<T, F>(items: Vec<T>, room_name: F) -> Vec<(T, WorldPosition)>
+where
+    F: Fn(&T) -> String,
+{
+    items
+        .into_iter()
+        .filter_map(|item| {
+            let name = room_name(&item);
+            match WorldPosition::parse_name(&name) {
+                Ok(room) => Some((item, room)),
+                Err(e) => {
+                    warn!("Skipping the flag in room {:?}, {}", name, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Complete once the room is claimed
+/// The expansion is helped until it has a spawn
+fn expand(state: &mut GameState, _flag: &Flag, room: WorldPosition) -> ExecutionResult {
+    let claimed = game::rooms::get(room.to_string().as_str())
+        .and_then(|room| room.controller())
+        .map(|controller| controller.my())
+        .unwrap_or(false);
+    if claimed {
+        return Ok(Status::Success);
+    }
+    state.expansion.insert(room);
+    Ok(Status::Running)
+}
+
+/// Complete once the room is visible and has no hostile creeps or structures
+fn attack(state: &mut GameState, _flag: &Flag, room: WorldPosition) -> ExecutionResult {
+    let cleared = game::rooms::get(room.to_string().as_str())
+        .map(|room| {
+            room.find(find::HOSTILE_CREEPS).is_empty()
+                && room.find(find::HOSTILE_STRUCTURES).is_empty()
+        })
+        .unwrap_or(false);
+    if cleared {
+        return Ok(Status::Success);
+    }
+    state.attack_targets.insert(room);
+    Ok(Status::Running)
+}
+
+/// Kept until the flag is removed by hand
+fn remote_mine(state: &mut GameState, _flag: &Flag, room: WorldPosition) -> ExecutionResult {
+    state.remote_mining.insert(room);
+    Ok(Status::Running)
+}
+
+/// Complete once the room is visible and there is no structure under the flag
+fn dismantle(_state: &mut GameState, flag: &Flag, room: WorldPosition) -> ExecutionResult {
+    if game::rooms::get(room.to_string().as_str()).is_none() {
+        return Ok(Status::Running);
+    }
+    let n_structures = js! {
+        return @{flag}.pos.lookFor(LOOK_STRUCTURES).length;
+    };
+    let n_structures = u32::try_from(n_structures)
+        .map_err(|e| ExecutionError::conversion(format!("structures {:?}", e)))?;
+    if n_structures == 0 {
+        Ok(Status::Success)
+    } else {
+        Ok(Status::Running)
+    }
+}
+
+/// Complete once the room has a storage
+fn storage(_state: &mut GameState, _flag: &Flag, room: WorldPosition) -> ExecutionResult {
+    let built = game::rooms::get(room.to_string().as_str())
+        .map(|room| room.storage().is_some())
+        .unwrap_or(false);
+    if built {
+        Ok(Status::Success)
+    } else {
+        Ok(Status::Running)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_malformed_rooms_are_skipped() {
+        let flags = vec![("bad", "sim"), ("good", "W1N1")];
+        let result = in_rooms(flags, |(_, room)| room.to_string());
+        assert_eq!(
+            result,
+            vec![(("good", "W1N1"), WorldPosition::parse_name("W1N1").unwrap())]
+        );
+    }
+
+    #[test]
+    fn test_prefixes_need_a_delimiter() {
+        assert_eq!(FlagCommand::of_name("mine"), Some(FlagCommand::RemoteMine));
+        assert_eq!(
+            FlagCommand::of_name("Mine:W1N1"),
+            Some(FlagCommand::RemoteMine)
+        );
+        assert_eq!(
+            FlagCommand::of_name("storage_2"),
+            Some(FlagCommand::Storage)
+        );
+        assert_eq!(FlagCommand::of_name("mineral_note"), None);
+        assert_eq!(FlagCommand::of_name("store_me"), None);
+        assert_eq!(FlagCommand::of_name("storageroom"), None);
+        assert_eq!(FlagCommand::of_name("Flag1"), None);
+    }
+}
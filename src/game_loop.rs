@@ -1,28 +1,68 @@
 use crate::constructions;
 use crate::creeps;
+use crate::defense;
 use crate::expansion;
 use crate::flags;
+use crate::logging;
 use crate::prelude::*;
-use crate::state::MemorySentinel;
-use crate::stats::save_stats;
+use crate::remote_mining;
+use crate::scheduler::{self, tick_budget, Priority, Subsystem};
+use crate::state::SegmentCache;
+use crate::stats::{save_stats, save_traces};
 use crate::structures::{spawns, towers};
-use crate::MAIN_SEGMENT;
+use crate::{CONFIG_RELOAD_INTERVAL, GAME_STATE_SAVE_INTERVAL, MAIN_SEGMENT};
 use log::Level::Info;
-use std::pin::Pin;
+use std::cell::RefCell;
 use stdweb::unstable::TryFrom;
 
+thread_local! {
+    static GAME_STATE: RefCell<SegmentCache<GameState>> =
+        RefCell::new(SegmentCache::new(MAIN_SEGMENT, GAME_STATE_SAVE_INTERVAL));
+}
+
 pub fn game_loop() {
     debug!("Loop starting! CPU: {}", screeps::game::cpu::get_used());
 
-    let mut game_state = GameState::read_from_segment_or_default(MAIN_SEGMENT);
-    let game_state = unsafe {
-        let mut_ref: Pin<&mut GameState> = Pin::as_mut(&mut game_state);
-        Pin::get_unchecked_mut(mut_ref)
-    };
+    GAME_STATE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        match cache.load(screeps::game::time()) {
+            Some(mut game_state) => run_tick(&mut game_state),
+            None => {
+                // Only read after a reset, the segments are loaded in the next tick
+                warn!("GameState segments are not loaded yet, running the towers only");
+                towers::run(&mut GameState::default())
+                    .map(|_| ())
+                    .unwrap_or_else(|e| warn!("Failed to run towers {}", e));
+            }
+        };
+    });
+}
 
-    let _sentinel = MemorySentinel::<GameState>::new(MAIN_SEGMENT as u8, &*game_state);
+/// Borrow the GameState between ticks, used by the console commands
+/// Fails if the segments are not loaded or the game loop is running
+pub fn with_game_state<R, F: FnOnce(&mut GameState) -> R>(f: F) -> Result<R, String> {
+    GAME_STATE.with(|cache| {
+        let mut cache = cache
+            .try_borrow_mut()
+            .map_err(|_| "GameState is in use by the game loop")?;
+        let mut game_state = cache
+            .load(screeps::game::time())
+            .ok_or("GameState segments are not loaded yet")?;
+        Ok(f(&mut game_state))
+    })
+}
 
-    trace!("Running");
+fn run_tick(game_state: &mut GameState) {
+    let time = screeps::game::time();
+    let reload = game_state
+        .config_time
+        .map(|t| time.wrapping_sub(t) >= CONFIG_RELOAD_INTERVAL)
+        .unwrap_or(true);
+    if reload {
+        game_state.config.reload();
+        game_state.config_time = Some(time);
+    }
+    logging::set_level(game_state.config.log_level());
 
     // screeps api `bucket` method panics in simulation
     let bucket = js! {
@@ -30,12 +70,23 @@ pub fn game_loop() {
     };
 
     let bucket = Option::<i32>::try_from(bucket).expect("Expected bucket to be a number");
+    if let Some(bucket) = bucket.filter(|b| *b < game_state.config.min_bucket) {
+        warn!("Bucket is empty ({}), skipping loop this tick", bucket);
+        return;
+    }
+
+    trace!("Running");
+    game_state.reset_tick_data();
 
     game_state.cpu_bucket = bucket.map(|x| x as i16);
-    run_game_logic(game_state);
+    run_game_logic(game_state, bucket);
 
     let bucket = bucket.unwrap_or(-1);
 
+    save_traces(game_state)
+        .map(|_| ())
+        .unwrap_or_else(|e| debug!("Traces were not saved {}", e));
+
     if log_enabled!(Info) {
         save_stats(
             screeps::game::time() as u32,
@@ -43,7 +94,7 @@ pub fn game_loop() {
             // Note that cpu stats won't take the stats saving into account
             screeps::game::cpu::get_used() as f32,
             bucket,
-            &game_state,
+            game_state,
         )
         .map(|_| {
             info!("Statistics saved!");
@@ -60,27 +111,95 @@ pub fn game_loop() {
     );
 }
 
+/// Subsystems in order of priority
+const SUBSYSTEMS: &[Subsystem] = &[
+    Subsystem {
+        name: "towers",
+        priority: Priority::Critical,
+        share: 0.1,
+        interval: 1,
+        run: towers::run,
+    },
+    Subsystem {
+        name: "creeps",
+        priority: Priority::Critical,
+        share: 0.6,
+        interval: 1,
+        run: creeps::run,
+    },
+    Subsystem {
+        name: "defense",
+        priority: Priority::Critical,
+        share: 0.02,
+        interval: 1,
+        run: defense::run,
+    },
+    Subsystem {
+        name: "spawns",
+        priority: Priority::Critical,
+        share: 0.05,
+        interval: 1,
+        run: spawns::run,
+    },
+    Subsystem {
+        name: "flags",
+        priority: Priority::Critical,
+        share: 0.02,
+        interval: 1,
+        run: flags::run,
+    },
+    Subsystem {
+        name: "constructions",
+        priority: Priority::Deferrable,
+        share: 0.15,
+        interval: 1,
+        run: constructions::run,
+    },
+    Subsystem {
+        name: "expansion",
+        priority: Priority::Deferrable,
+        share: 0.03,
+        interval: 1,
+        run: expansion::run,
+    },
+    Subsystem {
+        name: "remote_mining",
+        priority: Priority::Deferrable,
+        share: 0.02,
+        interval: 5,
+        run: remote_mining::run,
+    },
+    Subsystem {
+        name: "cleanup",
+        priority: Priority::Deferrable,
+        share: 0.05,
+        interval: 16,
+        run: cleanup_memory,
+    },
+];
+
 /// Call subsystems in order of priority
 /// Runs to completion even if a subsystem fails
-///
-/// TODO: GameResult object to return?
-fn run_game_logic(state: &mut GameState) {
-    towers::run(state)
-        .unwrap_or_else(|e| warn!("Failed to run towers {}", e));
-    creeps::run(state)
-        .unwrap_or_else(|e| warn!("Failed to run creeps {}", e));
-    spawns::run(state)
-        .unwrap_or_else(|e| warn!("Failed to run spawns {}", e));
-    constructions::run(state)
-        .unwrap_or_else(|e| warn!("Failed to run constructions {}", e));
-    flags::run(state)
-        .unwrap_or_else(|e| warn!("Failed to run flags {}", e));
-    expansion::run(state).unwrap_or_else(|e| warn!("Failed to expansion {}", e));
-
-    if screeps::game::time() % 16 == 0 {
-        state.cleanup_memory().unwrap_or_else(|e| {
-            error!("Failed to clean up memory {:?}", e);
-        });
-    }
+/// Deferrable subsystems are skipped if the CPU budget of the tick is spent
+fn run_game_logic(state: &mut GameState, bucket: Option<i32>) {
+    let limit = screeps::game::cpu::limit() as f32;
+    let tick_limit = screeps::game::cpu::tick_limit() as f32;
+    // There is no bucket in simulation
+    let budget = tick_budget(limit, tick_limit, bucket.unwrap_or(10_000));
+    debug!("CPU budget of the tick: {:.2}", budget);
+
+    scheduler::run(
+        state,
+        SUBSYSTEMS,
+        budget,
+        screeps::game::time(),
+        screeps::game::cpu::get_used,
+    );
 }
 
+fn cleanup_memory(state: &mut GameState) -> ExecutionResult {
+    state
+        .cleanup_memory()
+        .map_err(|e| format!("Failed to clean up memory {:?}", e))?;
+    Ok(Status::Success)
+}
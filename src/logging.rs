@@ -34,7 +34,8 @@ impl log::Log for JsNotify {
 
 pub fn setup_logging(verbosity: log::LevelFilter) {
     fern::Dispatch::new()
-        .level(verbosity)
+        // Filtered by the max level, so it can be changed at runtime
+        .level(log::LevelFilter::Trace)
         .format(|out, message, record| {
             out.finish(format_args!(
                 "({}) {}: {}",
@@ -55,4 +56,11 @@ pub fn setup_logging(verbosity: log::LevelFilter) {
         )
         .apply()
         .expect("expected setup_logging to only ever be called once per instance");
+    set_level(verbosity);
+}
+
+pub fn set_level(verbosity: log::LevelFilter) {
+    if log::max_level() != verbosity {
+        log::set_max_level(verbosity);
+    }
 }
@@ -1,9 +1,10 @@
 #![recursion_limit = "256"]
+// Clippy gates the correctness, suspicious and perf lints, the style ones post-date the code
+#![allow(clippy::style, clippy::complexity)]
+// Errors carry their message inline, see `bt::error`
+#![allow(clippy::result_large_err)]
 extern crate fern;
 #[macro_use]
-extern crate num_derive;
-extern crate num;
-#[macro_use]
 extern crate log;
 extern crate screeps;
 #[macro_use]
@@ -11,42 +12,71 @@ extern crate stdweb;
 #[macro_use]
 extern crate serde;
 extern crate arrayvec;
+extern crate bincode;
 extern crate serde_json;
 #[macro_use]
 extern crate lazy_static;
 
 mod bt;
 mod collections;
+mod config;
+mod console;
 mod constructions;
 mod creeps;
+mod defense;
 mod flags;
 mod game_loop;
 mod state;
 mod logging;
 mod expansion;
 mod prelude;
+mod remote_mining;
 mod rooms;
+mod scheduler;
 mod stats;
 mod structures;
 
 use game_loop::game_loop;
 use screeps::raw_memory;
+use std::ops::Range;
 
 pub const MAIN_SEGMENT: u32 = 0;
 pub const CONSTRUCTIONS_SEGMENT: u32 = 1;
 pub const STATISTICS_SEGMENT: u32 = 2;
+pub const TRACES_SEGMENT: u32 = 3;
+/// Segments holding the large maps of the GameState
+/// At most 10 segments can be used in a tick, including the ones above and the backups
+pub const SHARD_SEGMENTS: Range<u32> = 4..8;
+/// Segments failing to load are kept in segment `id + BACKUP_SEGMENT_OFFSET`
+pub const BACKUP_SEGMENT_OFFSET: u32 = 50;
+/// Segments left unused by the ones above, so the backups can be written
+pub const MAX_BACKUPS_PER_TICK: u32 = 2;
+/// The GameState is kept on the heap and written to its segments at most every N ticks
+/// Up to N ticks of changes are lost when the VM is reset
+pub const GAME_STATE_SAVE_INTERVAL: u32 = 10;
 pub const VERSION: &'static str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/VERSION"));
-pub const COLLECT_STATS: bool = false;
-pub const USERNAME: &'static str = "Frenetiq";
+/// Read the structures, energy and hostiles of the rooms every N ticks
+pub const ROOM_STATE_INTERVAL: u32 = 5;
+/// Read the config from Memory every N ticks, reading it forces the parsing of Memory
+/// `xenos.reloadConfig()` applies changes right away
+pub const CONFIG_RELOAD_INTERVAL: u32 = 20;
 
 lazy_static! {
-    pub static ref DEPLOYMENT_TIME: u32 = { screeps::game::time() };
+    pub static ref DEPLOYMENT_TIME: u32 = screeps::game::time();
 }
 
 /// Run initialisation tasks
 /// These are only called on script restart!
 fn initialize() {
-    raw_memory::set_active_segments(&[MAIN_SEGMENT, STATISTICS_SEGMENT, CONSTRUCTIONS_SEGMENT]);
+    let mut segments = vec![
+        MAIN_SEGMENT,
+        STATISTICS_SEGMENT,
+        CONSTRUCTIONS_SEGMENT,
+        TRACES_SEGMENT,
+    ];
+    segments.extend(SHARD_SEGMENTS);
+    raw_memory::set_active_segments(&segments);
+    console::register();
 }
 
 fn main() {
@@ -63,13 +93,6 @@ fn main() {
 
         module.exports.loop = function() {
             try {
-                const bucket = Game.cpu.bucket;
-                if (bucket < 500) {
-                    console.log("Bucket:", bucket);
-                    console_error("Bucket is empty, skipping loop this tick");
-                    return;
-                }
-
                 game_loop();
 
             } catch (error) {
@@ -90,3 +113,10 @@ fn main() {
     }
 }
 
+
+/// Off wasm `js!` calls into emscripten, native builds and the unit tests skip the snippets
+#[cfg(not(any(target_arch = "wasm32", target_os = "emscripten")))]
+#[no_mangle]
+pub extern "C" fn emscripten_asm_const_int(_code: *const u8) -> i32 {
+    0
+}
@@ -0,0 +1,20 @@
+//! Request long range harvesters for the rooms with containers
+//! The harvesters pick the neighbouring room to mine, see `creeps::lrh`
+//!
+use crate::creeps::Role;
+use crate::prelude::*;
+use crate::structures::spawns::request_missing;
+use screeps::constants::find;
+use screeps::game;
+
+const LRH_REQUEST_TTL: u32 = 200;
+
+pub fn run<'a>(state: &mut GameState) -> ExecutionResult {
+    for room in game::rooms::values() {
+        if room.find(find::MY_SPAWNS).is_empty() {
+            continue;
+        }
+        request_missing(state, &room, Role::Lrh, "remote_mining", LRH_REQUEST_TTL);
+    }
+    Ok(Status::Success)
+}
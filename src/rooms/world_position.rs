@@ -88,7 +88,7 @@ impl WorldPosition {
         let mut result = ArrayString::default();
 
         for (num, pre) in [x, y].iter().zip(prefixes.iter()) {
-            let num = num.abs() as u16;
+            let num = num.unsigned_abs();
             let len = len_of_num(num);
             result.push(*pre);
             for i in (0..len).rev() {
@@ -0,0 +1,124 @@
+//! Distribute the CPU of a tick among the top level subsystems
+//! Deferrable subsystems are postponed to later ticks when the budget of the tick is spent
+//!
+use crate::prelude::*;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Runs every time it's due, regardless of the budget
+    Critical,
+    /// Runs only if its share of the budget is still available
+    Deferrable,
+}
+
+pub struct Subsystem {
+    pub name: &'static str,
+    pub priority: Priority,
+    /// Fraction of the tick's budget the subsystem is expected to use
+    pub share: f32,
+    /// Run at most once every `interval` ticks
+    pub interval: u32,
+    pub run: fn(&mut GameState) -> ExecutionResult,
+}
+
+/// Bookkeeping of the scheduler, persisted between ticks
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchedulerState {
+    /// Structure: subsystem -> game time of the last run
+    last_run: BTreeMap<String, u32>,
+    /// Structure: subsystem -> number of times it was deferred
+    pub deferrals: BTreeMap<String, u32>,
+}
+
+impl SchedulerState {
+    fn is_due(&self, subsystem: &Subsystem, time: u32) -> bool {
+        self.last_run
+            .get(subsystem.name)
+            .map(|last| time.wrapping_sub(*last) >= subsystem.interval)
+            .unwrap_or(true)
+    }
+}
+
+/// CPU the subsystems may use in a tick
+/// A full bucket allows 1.5 times the limit, an empty one half of it
+pub fn tick_budget(limit: f32, tick_limit: f32, bucket: i32) -> f32 {
+    let fill = bucket.max(0).min(10_000) as f32 / 10_000.0;
+    (limit * (0.5 + fill)).min(tick_limit)
+}
+
+/// Run the due subsystems in order
+/// `clock` returns the CPU used so far in the tick
+pub fn run(
+    state: &mut GameState,
+    subsystems: &[Subsystem],
+    budget: f32,
+    time: u32,
+    clock: fn() -> f64,
+) {
+    for (i, subsystem) in subsystems.iter().enumerate() {
+        if !state.scheduler.is_due(subsystem, time) {
+            continue;
+        }
+        let start = clock() as f32;
+        let allowance = subsystem.share * budget;
+        if subsystem.priority == Priority::Deferrable && start + allowance > budget {
+            debug!(
+                "Deferring {}, CPU used: {:.2} budget: {:.2}",
+                subsystem.name, start, budget
+            );
+            *state
+                .scheduler
+                .deferrals
+                .entry(subsystem.name.to_owned())
+                .or_insert(0) += 1;
+            continue;
+        }
+        state
+            .scheduler
+            .last_run
+            .insert(subsystem.name.to_owned(), time);
+
+        // Leave enough for the critical subsystems that run later
+        let reserved: f32 = subsystems[i + 1..]
+            .iter()
+            .filter(|s| s.priority == Priority::Critical)
+            .map(|s| s.share * budget)
+            .sum();
+        state.cpu_deadline = Some((budget - reserved).max(start + allowance));
+
+        log_failure(subsystem.name, (subsystem.run)(state));
+        state.cpu_deadline = None;
+
+        let used = clock() as f32 - start;
+        if used > allowance {
+            debug!(
+                "{} used {:.2} CPU, over its allowance of {:.2}",
+                subsystem.name, used, allowance
+            );
+        }
+    }
+}
+
+/// Expected outcomes (e.g. skipping a subsystem in this tick) are not worth a warning
+fn log_failure(subsystem: &str, result: ExecutionResult) {
+    match result {
+        Ok(_) => {}
+        Err(ref e) if e.is_expected() => debug!("Did not run {}: {}", subsystem, e),
+        Err(e) => warn!("Failed to run {} {}", subsystem, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_budget() {
+        assert_eq!(tick_budget(20.0, 500.0, 10_000), 30.0);
+        assert_eq!(tick_budget(20.0, 500.0, 5_000), 20.0);
+        assert_eq!(tick_budget(20.0, 500.0, 0), 10.0);
+        assert_eq!(tick_budget(20.0, 500.0, -1), 10.0);
+        assert_eq!(tick_budget(20.0, 25.0, 10_000), 25.0);
+    }
+}
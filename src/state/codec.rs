@@ -0,0 +1,158 @@
+//! Encodings of the data in the memory segments
+//! Segments hold strings, binary data is packed 15 bits per character
+//! so every character is a valid UTF-16 code unit outside of the surrogate range
+//!
+use bincode::{self, Options};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// First character of the packed range, the last one is `0xCDFF`
+const CHAR_OFFSET: u32 = 0x4E00;
+const BITS_PER_CHAR: u32 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// Human readable, can be migrated between versions
+    Json,
+    /// Bincode packed into characters, several times smaller than JSON
+    /// Not self describing: the data can not be migrated and the type has to
+    /// serialize every field it deserializes
+    Binary,
+}
+
+pub fn to_binary<T: Serialize>(item: &T) -> Result<String, bincode::Error> {
+    let bytes = bincode::DefaultOptions::new().serialize(item)?;
+    Ok(pack(&bytes))
+}
+
+pub fn from_binary<T: DeserializeOwned>(data: &str) -> Result<T, bincode::Error> {
+    let bytes = unpack(data)
+        .ok_or_else(|| Box::new(bincode::ErrorKind::Custom("invalid packed data".to_owned())))?;
+    let item = bincode::DefaultOptions::new().deserialize(&bytes)?;
+    Ok(item)
+}
+
+/// Pack the bytes into characters of 15 bits
+/// The last character holds the number of padding bits in the one before it
+fn pack(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(bytes.len() * 8 / BITS_PER_CHAR as usize + 2);
+    let mut buffer = 0u32;
+    let mut n_bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        n_bits += 8;
+        if n_bits >= BITS_PER_CHAR {
+            n_bits -= BITS_PER_CHAR;
+            result.push(to_char(buffer >> n_bits));
+            buffer &= (1 << n_bits) - 1;
+        }
+    }
+    let padding = if n_bits > 0 {
+        let padding = BITS_PER_CHAR - n_bits;
+        result.push(to_char(buffer << padding));
+        padding
+    } else {
+        0
+    };
+    result.push(to_char(padding));
+    result
+}
+
+/// Returns None if the data holds characters outside of the packed range
+fn unpack(data: &str) -> Option<Vec<u8>> {
+    let mut chars = data
+        .chars()
+        .map(|c| {
+            (c as u32)
+                .checked_sub(CHAR_OFFSET)
+                .filter(|x| *x < 1 << BITS_PER_CHAR)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let padding = chars.pop()?;
+    if padding >= BITS_PER_CHAR {
+        return None;
+    }
+
+    let n_bits = chars.len() as u32 * BITS_PER_CHAR - padding;
+    let mut result = Vec::with_capacity(n_bits as usize / 8);
+    let mut buffer = 0u32;
+    let mut buffered = 0;
+    for value in chars {
+        buffer = (buffer << BITS_PER_CHAR) | value;
+        buffered += BITS_PER_CHAR;
+        while buffered >= 8 && result.len() < n_bits as usize / 8 {
+            buffered -= 8;
+            result.push((buffer >> buffered) as u8);
+            buffer &= (1 << buffered) - 1;
+        }
+    }
+    Some(result)
+}
+
+fn to_char(value: u32) -> char {
+    std::char::from_u32(CHAR_OFFSET + value).expect("Packed characters are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    struct Rooms {
+        queues: BTreeMap<String, Vec<(i16, i16)>>,
+        counts: BTreeMap<String, [u8; 4]>,
+    }
+
+    fn rooms() -> Rooms {
+        let mut result = Rooms::default();
+        for i in 0..20 {
+            let name = format!("W{}N{}", i, 20 - i);
+            let queue = (0..32).map(|j| (j * 3 % 50, (i + j) % 50)).collect();
+            result.queues.insert(name.clone(), queue);
+            result.counts.insert(name, [i as u8, 0, 2, 1]);
+        }
+        result
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        for len in 0..64 {
+            let bytes = (0..len).map(|i| (i * 37 + 255) as u8).collect::<Vec<_>>();
+            let packed = pack(&bytes);
+            assert!(packed
+                .chars()
+                .all(|c| c as u32 >= CHAR_OFFSET && (c as u32) < 0xD800));
+            assert_eq!(unpack(&packed), Some(bytes));
+        }
+    }
+
+    #[test]
+    fn test_invalid_data_is_rejected() {
+        assert_eq!(unpack(""), None);
+        assert_eq!(unpack("{}"), None);
+        assert!(from_binary::<Rooms>("\u{4E00}\u{4E0F}").is_err());
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let data = rooms();
+        let packed = to_binary(&data).unwrap();
+        assert_eq!(from_binary::<Rooms>(&packed).unwrap(), data);
+    }
+
+    #[test]
+    fn test_binary_is_smaller_than_json() {
+        let data = rooms();
+        // Screeps limits the segments by the number of characters
+        let json = serde_json::to_string(&data).unwrap().chars().count();
+        let binary = to_binary(&data).unwrap().chars().count();
+        assert!(
+            binary * 3 < json,
+            "binary: {} characters, json: {} characters",
+            binary,
+            json
+        );
+    }
+}
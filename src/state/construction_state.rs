@@ -1,5 +1,6 @@
 use crate::constructions::construction_matrix::ConstructionMatrix;
-use screeps::raw_memory;
+use crate::state::codec::Codec;
+use crate::state::segment::{Migration, Versioned};
 use std::collections::BTreeMap;
 use crate::collections::{FlagGrid5050};
 
@@ -9,20 +10,14 @@ pub struct ConstructionState {
     pub connections: BTreeMap<String, FlagGrid5050>,
 }
 
-impl ConstructionState {
-    pub fn read_from_segment_or_default(segment: u32) -> Self {
-        raw_memory::get_segment(segment)
-            .and_then(|string| {
-                serde_json::from_str(&string)
-                    .map_err(|e| {
-                        error!(
-                            "Failed to deserialize construction_state from segment {:?}",
-                            e
-                        );
-                    })
-                    .ok()
-            })
-            .unwrap_or_default()
+impl Versioned for ConstructionState {
+    fn migrations() -> &'static [Migration] {
+        &[]
+    }
+
+    /// The matrices' queues take most of the segment in JSON
+    fn codec() -> Codec {
+        Codec::Binary
     }
 }
 
@@ -0,0 +1,397 @@
+//! Typed memory of the creeps
+//! Serialized with short keys to keep the main segment small
+//!
+use super::path_cache::CachedPath;
+use crate::bt::NodePath;
+use crate::creeps::Role;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::Serializer;
+use serde_json::{self, Map, Value};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CreepMemory {
+    #[serde(rename = "r")]
+    #[serde(with = "role_code")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+
+    /// Name of the room the creep belongs to
+    #[serde(rename = "h")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub home: Option<String>,
+
+    /// Is the creep gathering resources
+    #[serde(rename = "l")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_false")]
+    pub loading: bool,
+
+    /// Id of the object or name of the room the creep is working on
+    #[serde(rename = "t")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+
+    /// Path of the running node of the creep's behaviour tree
+    #[serde(rename = "p")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub task: NodePath,
+
+    /// Timestamps of the time based decorators
+    #[serde(rename = "tm")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub timers: BTreeMap<String, u32>,
+
+    /// Path the creep is walking
+    #[serde(rename = "mv")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<CachedPath>,
+
+    #[serde(rename = "x")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "RoleMemory::is_none")]
+    pub payload: RoleMemory,
+}
+
+/// Memory only used by specific roles
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum RoleMemory {
+    None,
+    #[serde(rename = "H")]
+    Harvester(HarvesterMemory),
+    #[serde(rename = "LRH")]
+    Lrh(LrhMemory),
+    #[serde(rename = "LRW")]
+    Lrw(LrwMemory),
+    #[serde(rename = "C")]
+    Conqueror(ConquerorMemory),
+    #[serde(rename = "D")]
+    Defender(DefenderMemory),
+    #[serde(rename = "M")]
+    Miner(MinerMemory),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct HarvesterMemory {
+    /// Id of the source being harvested
+    #[serde(rename = "s")]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct LrhMemory {
+    #[serde(rename = "r")]
+    pub target_room: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct LrwMemory {
+    #[serde(rename = "r")]
+    pub target_room: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ConquerorMemory {
+    #[serde(rename = "r")]
+    pub target_room: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct DefenderMemory {
+    #[serde(rename = "a")]
+    pub attack_target: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct MinerMemory {
+    /// Id of the container the miner is parked on
+    #[serde(rename = "c")]
+    pub container: Option<String>,
+}
+
+/// Payload of a single role in `RoleMemory`
+pub trait RolePayload: Default + Sized {
+    fn get(memory: &RoleMemory) -> Option<&Self>;
+    fn get_mut(memory: &mut RoleMemory) -> Option<&mut Self>;
+    fn wrap(self) -> RoleMemory;
+}
+
+macro_rules! impl_role_payload {
+    ($payload: ident, $variant: ident) => {
+        impl RolePayload for $payload {
+            fn get(memory: &RoleMemory) -> Option<&Self> {
+                match memory {
+                    RoleMemory::$variant(payload) => Some(payload),
+                    _ => None,
+                }
+            }
+
+            fn get_mut(memory: &mut RoleMemory) -> Option<&mut Self> {
+                match memory {
+                    RoleMemory::$variant(payload) => Some(payload),
+                    _ => None,
+                }
+            }
+
+            fn wrap(self) -> RoleMemory {
+                RoleMemory::$variant(self)
+            }
+        }
+    };
+}
+
+impl_role_payload!(HarvesterMemory, Harvester);
+impl_role_payload!(LrhMemory, Lrh);
+impl_role_payload!(LrwMemory, Lrw);
+impl_role_payload!(ConquerorMemory, Conqueror);
+impl_role_payload!(DefenderMemory, Defender);
+impl_role_payload!(MinerMemory, Miner);
+
+impl Default for RoleMemory {
+    fn default() -> Self {
+        RoleMemory::None
+    }
+}
+
+impl RoleMemory {
+    pub fn is_none(&self) -> bool {
+        match self {
+            RoleMemory::None => true,
+            _ => false,
+        }
+    }
+}
+
+impl CreepMemory {
+    pub fn home(&self) -> Option<&str> {
+        self.home.as_deref()
+    }
+
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    pub fn payload<P: RolePayload>(&self) -> Option<&P> {
+        P::get(&self.payload)
+    }
+
+    /// Payload of the role `P`
+    /// Replaces the payload of any other role
+    pub fn payload_mut<P: RolePayload>(&mut self) -> &mut P {
+        if P::get(&self.payload).is_none() {
+            self.payload = P::default().wrap();
+        }
+        P::get_mut(&mut self.payload).unwrap()
+    }
+
+    /// Convert the string keyed memory used before the typed schema
+    fn from_legacy(memory: &Map<String, Value>) -> Self {
+        let string = |key: &str| memory.get(key).and_then(|v| v.as_str()).map(String::from);
+        let mut result = Self {
+            role: memory
+                .get("role")
+                .and_then(|v| v.as_u64())
+                .and_then(|code| role_code::from_code(code as u8)),
+            home: string("home"),
+            loading: memory
+                .get("loading")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            target: string("target"),
+            task: memory
+                .get("task")
+                .and_then(|v| v.as_array())
+                .map(|path| {
+                    path.iter()
+                        .filter_map(|i| i.as_u64())
+                        .map(|i| i as u8)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            timers: memory
+                .get("timers")
+                .and_then(|v| v.as_object())
+                .map(|timers| {
+                    timers
+                        .iter()
+                        .filter_map(|(k, v)| v.as_u64().map(|v| (k.clone(), v as u32)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            path: None,
+            payload: RoleMemory::None,
+        };
+        if let Some(source) = string("harvest_target") {
+            result.payload_mut::<HarvesterMemory>().source = Some(source);
+        }
+        if let Some(room) = string("harvest_target_room") {
+            result.payload_mut::<LrhMemory>().target_room = Some(room);
+        }
+        if let Some(room) = string("target_room") {
+            result.payload_mut::<LrwMemory>().target_room = Some(room);
+        }
+        if let Some(room) = string("conquest_target") {
+            result.payload_mut::<ConquerorMemory>().target_room = Some(room);
+        }
+        if let Some(target) = string("attack_target") {
+            result.payload_mut::<DefenderMemory>().attack_target = Some(target);
+        }
+        result
+    }
+}
+
+/// Keys of the string keyed creep memory, none of them is used by the typed schema
+const LEGACY_KEYS: &[&str] = &[
+    "role",
+    "home",
+    "loading",
+    "target",
+    "task",
+    "timers",
+    "harvest_target",
+    "harvest_target_room",
+    "target_room",
+    "conquest_target",
+    "attack_target",
+];
+
+/// Migration of the GameState converting the string keyed creep memories
+pub fn migrate_legacy_creep_memory(mut state: Value) -> Result<Value, String> {
+    let memories = match state.get_mut("creep_memory") {
+        Some(Value::Object(memories)) => memories,
+        Some(_) => Err("creep_memory is not an object")?,
+        None => return Ok(state),
+    };
+    for (name, memory) in memories.iter_mut() {
+        let migrated = match memory {
+            Value::Object(ref map) if LEGACY_KEYS.iter().any(|k| map.contains_key(*k)) => {
+                debug!("Migrating the memory of creep {}", name);
+                CreepMemory::from_legacy(map)
+            }
+            _ => continue,
+        };
+        *memory = serde_json::to_value(migrated).map_err(|e| e.to_string())?;
+    }
+    Ok(state)
+}
+
+/// Deserialize creep memories, dropping the entries that fail to deserialize
+pub fn deserialize_creep_memories<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, CreepMemory>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let memories = BTreeMap::<String, Value>::deserialize(deserializer)?;
+    let result = memories
+        .into_iter()
+        .filter_map(|(name, memory)| {
+            serde_json::from_value(memory)
+                .map_err(|e| error!("Failed to deserialize memory of {} {:?}", name, e))
+                .ok()
+                .map(|memory| (name, memory))
+        })
+        .collect();
+    Ok(result)
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Serialize roles by their numeric value
+mod role_code {
+    use super::*;
+
+    pub fn from_code(code: u8) -> Option<Role> {
+        if code <= Role::Miner as u8 {
+            Some(Role::from(code))
+        } else {
+            None
+        }
+    }
+
+    pub fn serialize<S: Serializer>(role: &Option<Role>, serializer: S) -> Result<S::Ok, S::Error> {
+        match role {
+            Some(role) => serializer.serialize_u8(*role as u8),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Role>, D::Error> {
+        let code = Option::<u8>::deserialize(deserializer)?;
+        Ok(code.and_then(from_code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_round_trip() {
+        let mut memory = CreepMemory {
+            role: Some(Role::Harvester),
+            home: Some("W1N1".to_owned()),
+            task: vec![0, 2],
+            ..CreepMemory::default()
+        };
+        memory.payload_mut::<HarvesterMemory>().source = Some("abc".to_owned());
+
+        let json = serde_json::to_string(&memory).unwrap();
+        assert_eq!(
+            json,
+            r#"{"r":2,"h":"W1N1","p":[0,2],"x":{"H":{"s":"abc"}}}"#
+        );
+
+        let result: CreepMemory = serde_json::from_str(&json).unwrap();
+        assert_eq!(result, memory);
+    }
+
+    #[test]
+    fn test_legacy_memory_is_migrated() {
+        let state = serde_json::json!({
+            "scout_intel": {},
+            "creep_memory": {
+                "old": {
+                    "role": 5,
+                    "home": "W1N1",
+                    "loading": true,
+                    "task": [1],
+                    "harvest_target_room": "W2N1"
+                },
+                "new": {"r": 9, "x": {"D": {"a": "enemy"}}}
+            }
+        });
+        let state = migrate_legacy_creep_memory(state).unwrap();
+        let memories: BTreeMap<String, CreepMemory> =
+            serde_json::from_value(state["creep_memory"].clone()).unwrap();
+
+        let old = &memories["old"];
+        assert_eq!(old.role, Some(Role::Lrh));
+        assert_eq!(old.home.as_deref(), Some("W1N1"));
+        assert!(old.loading);
+        assert_eq!(old.task, vec![1]);
+        assert_eq!(
+            old.payload::<LrhMemory>()
+                .and_then(|p| p.target_room.clone()),
+            Some("W2N1".to_owned())
+        );
+
+        let new = &memories["new"];
+        assert_eq!(new.role, Some(Role::Defender));
+        assert_eq!(
+            new.payload::<DefenderMemory>()
+                .and_then(|p| p.attack_target.clone()),
+            Some("enemy".to_owned())
+        );
+    }
+}
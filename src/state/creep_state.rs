@@ -1,138 +1,120 @@
-use crate::creeps::{Role, TASK};
 use crate::prelude::*;
-use num::ToPrimitive;
 use screeps::Creep;
 use screeps::RoomObjectProperties;
-use serde_json::Value;
 use std::fmt::{Debug, Formatter};
 
-pub struct CreepState {
+/// Borrows the GameState for the execution of a single creep
+/// The creep's memory is reached through the GameState, so it can not be aliased
+pub struct CreepState<'a> {
     creep: Creep,
     creep_name: String,
     world_position: WorldPosition,
-    memory: *mut CreepMemoryEntry,
-    game_state: *mut GameState,
+    game_state: &'a mut GameState,
+    tree_path: TreePath,
+    node_timers: NodeTimers,
+    tracer: Option<Tracer>,
 }
 
-impl Clone for CreepState {
+impl<'a> Clone for CreepState<'a> {
     fn clone(&self) -> Self {
         panic!("Do not clone CreepState objects, the trait impl is provided so the Tasks are cloneable");
     }
 }
 
-impl CreepState {
-    /// Contract: GameState must live for the lifetime of this object
-    pub fn new(creep: Creep, game_state: &mut GameState) -> Self {
+impl<'a> CreepState<'a> {
+    pub fn new(creep: Creep, game_state: &'a mut GameState) -> Self {
         let creep_name = creep.name();
-        let memory = game_state.creep_memory_entry(CreepName(creep_name.as_str())) as *mut _;
-        let game_state = game_state as *mut _;
+        let (tree_path, node_timers) = {
+            let memory = game_state.creep_memory_entry(CreepName(creep_name.as_str()));
+            let tree_path = TreePath::resume_from(memory.task.iter().cloned());
+            let node_timers = NodeTimers::new(screeps::game::time(), memory.timers.clone());
+            (tree_path, node_timers)
+        };
         Self {
             world_position: WorldPosition::from(creep.room()),
             creep,
             creep_name,
             game_state,
-            memory,
+            tree_path,
+            node_timers,
+            tracer: None,
         }
     }
 
-    pub fn current_room(&self) -> WorldPosition {
-        self.world_position
+    /// Record the execution of the creep's behaviour tree
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
     }
 
-    pub fn creep_memory_remove(&mut self, key: &str) {
-        let memory = unsafe { &mut *self.memory };
-        memory.remove(key);
+    /// Save the path of the running node and the decorator timers
+    /// So the tree is resumed in the next tick
+    pub fn save_tree_state(&mut self) {
+        let path = self.tree_path.running().map(|path| path.to_vec());
+        self.node_timers.retain_live(path.as_deref());
+        let timers = self.node_timers.timers.clone();
+        let memory = self.memory_mut();
+        memory.task = path.unwrap_or_default();
+        memory.timers = timers;
     }
 
-    pub fn creep_memory_set<T: Into<Value>>(&mut self, key: &str, value: T) {
-        let val: Value = value.into();
-        let memory = unsafe { &mut *self.memory };
-        memory.insert(key.to_owned(), val);
+    pub fn current_room(&self) -> WorldPosition {
+        self.world_position
     }
 
-    #[allow(unused)]
-    pub fn creep_memory_mut<'a, T>(&'a mut self, key: &str) -> Option<&'a mut T>
-    where
-        &'a mut T: From<&'a mut Value>,
-    {
-        let memory = unsafe { &mut *self.memory };
-        memory.get_mut(key).map(|value| value.into())
+    pub fn memory<'b>(&'b self) -> &'b CreepMemory {
+        self.game_state
+            .creep_memory_get(CreepName(self.creep_name.as_str()))
+            .expect("CreepState::new inserts the memory of the creep")
     }
 
-    #[allow(unused)]
-    pub fn creep_memory_get<'a, T>(&'a self, key: &str) -> Option<&'a T>
-    where
-        &'a T: From<&'a Value>,
-    {
-        let memory = unsafe { &*self.memory };
-        memory.get(key).map(|value| value.into())
+    pub fn memory_mut<'b>(&'b mut self) -> &'b mut CreepMemory {
+        self.game_state
+            .creep_memory_entry(CreepName(self.creep_name.as_str()))
     }
 
-    pub fn get_game_state<'a>(&'a self) -> &'a GameState {
-        unsafe { &*self.game_state }
+    pub fn get_game_state<'b>(&'b self) -> &'b GameState {
+        self.game_state
     }
 
-    pub fn mut_game_state<'a>(&'a mut self) -> *mut GameState {
+    /// The creep's own memory can not be borrowed while the GameState is
+    pub fn mut_game_state<'b>(&'b mut self) -> &'b mut GameState {
         self.game_state
     }
 
-    pub fn creep_name<'a>(&'a self) -> CreepName<'a> {
+    pub fn creep_name<'b>(&'b self) -> CreepName<'b> {
         CreepName(self.creep_name.as_str())
     }
 
-    pub fn creep<'a>(&'a self) -> &'a Creep {
+    pub fn creep<'b>(&'b self) -> &'b Creep {
         &self.creep
     }
+}
 
-    pub fn creep_memory_role(&self, key: &str) -> Option<Role> {
-        let memory = unsafe { &mut *self.memory };
-        memory
-            .get(key)
-            .and_then(|value| value.as_i64())
-            .map(|x: i64| Role::from(x as u8))
-    }
-
-    pub fn creep_memory_string<'a>(&'a self, key: &str) -> Option<&'a str> {
-        let memory = unsafe { &mut *self.memory };
-        memory.get(key).and_then(|value| value.as_str())
+impl<'a> TaskInput for CreepState<'a> {
+    fn cpu_bucket(&self) -> Option<i16> {
+        self.get_game_state().cpu_bucket()
     }
 
-    pub fn creep_memory_bool(&self, key: &str) -> Option<bool> {
-        let memory = unsafe { &mut *self.memory };
-        memory.get(key).and_then(|value| value.as_bool())
+    fn tree_path(&mut self) -> Option<&mut TreePath> {
+        Some(&mut self.tree_path)
     }
 
-    pub fn creep_memory_i64(&self, key: &str) -> Option<i64> {
-        let memory = unsafe { &mut *self.memory };
-        memory.get(key).and_then(|value| value.as_i64())
+    fn node_timers(&mut self) -> Option<&mut NodeTimers> {
+        Some(&mut self.node_timers)
     }
-}
 
-impl TaskInput for CreepState {
-    fn cpu_bucket(&self) -> Option<i16> {
-        self.get_game_state().cpu_bucket()
+    fn tracer(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
     }
 }
 
-impl Debug for CreepState {
+impl<'a> Debug for CreepState<'a> {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         write!(f, "CreepState for creep {}", self.creep_name)?;
         Ok(())
     }
 }
-
-pub trait WithStateSave<'a> {
-    fn with_state_save<T: ToPrimitive + 'a>(self, task_id: T) -> Task<'a, CreepState>;
-}
-
-impl<'a> WithStateSave<'a> for Task<'a, CreepState> {
-    fn with_state_save<T: 'a + ToPrimitive>(self, task_id: T) -> Task<'a, CreepState> {
-        self.with_post_process(move |state: &mut CreepState, res: ExecutionResult| {
-            if let Ok(_) = res {
-                state.creep_memory_set(TASK, task_id.to_u32().unwrap_or(0));
-            }
-            res
-        })
-    }
-}
-
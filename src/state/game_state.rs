@@ -1,14 +1,19 @@
+use crate::config::Config;
 use crate::creeps::roles::Role;
-use crate::creeps::{CreepExecutionStats, CREEP_ROLE, HOME_ROOM};
+use crate::creeps::spawn_info::role_quotas;
+use crate::creeps::CreepExecutionStats;
+use crate::creeps::{lifetime, logistics};
 use crate::prelude::*;
-use screeps::{raw_memory, Room};
-use serde_json::{self, Value};
+use crate::scheduler::SchedulerState;
+use crate::state::segment::{self, Migration, SegmentError, Versioned};
+use crate::state::shards::{self, ShardWriter, MAX_SHARD_LEN};
+use crate::{ROOM_STATE_INTERVAL, SHARD_SEGMENTS};
+use screeps::constants::find;
+use screeps::Room;
 use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
-use std::pin::Pin;
+use std::ops::Range;
 
-pub type CreepMemoryEntry = BTreeMap<String, Value>;
-pub type CreepMemory = BTreeMap<String, CreepMemoryEntry>;
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 /// Holds information about the global state of the game
@@ -18,23 +23,51 @@ pub struct GameState {
     #[serde(default)]
     pub cpu_bucket: Option<i16>,
 
+    /// Reloaded from Memory every `CONFIG_RELOAD_INTERVAL` ticks
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    #[serde(default)]
+    pub config: Config,
+
+    /// Game time the config was last reloaded, None after a reset
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    #[serde(default)]
+    pub config_time: Option<u32>,
+
     /// Count creeps in rooms
     /// Structure: room -> role -> n
     #[serde(skip_serializing)]
     #[serde(default)]
     creep_count_by_room: BTreeMap<WorldPosition, BTreeMap<Role, i8>>,
 
+    /// Cached state of the visible rooms, refreshed every `ROOM_STATE_INTERVAL` ticks
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    #[serde(default)]
+    rooms: BTreeMap<WorldPosition, RoomState>,
+
     /// Information about rooms
     /// Structure: room -> info
+    /// Kept in the shard segments
+    #[serde(skip_serializing)]
+    #[serde(default)]
     pub scout_intel: BTreeMap<WorldPosition, ScoutInfo>,
 
     /// Number of LRH per room
     /// In directions: [N, W, S, E]
+    /// Kept in the shard segments
+    #[serde(skip_serializing)]
+    #[serde(default)]
     pub long_range_harvesters: BTreeMap<WorldPosition, [u8; 4]>,
 
     /// Holds the creep memory objects
     /// Structure: name -> data
-    creep_memory: CreepMemory,
+    /// Kept in the shard segments
+    #[serde(skip_serializing)]
+    #[serde(deserialize_with = "deserialize_creep_memories")]
+    #[serde(default)]
+    creep_memory: BTreeMap<String, CreepMemory>,
 
     /// Data about creep task execution in a tick
     #[serde(skip_serializing)]
@@ -44,6 +77,66 @@ pub struct GameState {
 
     /// Rooms targeted for expansion
     pub expansion: BTreeSet<WorldPosition>,
+
+    /// Rooms to clear of hostiles, set by the flags every tick
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    #[serde(default)]
+    pub attack_targets: BTreeSet<WorldPosition>,
+
+    /// Rooms to send long range harvesters to, set by the flags every tick
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    #[serde(default)]
+    pub remote_mining: BTreeSet<WorldPosition>,
+
+    /// Creeps requested by the subsystems
+    /// Structure: room -> queue
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    #[serde(default)]
+    spawn_queues: BTreeMap<WorldPosition, SpawnQueue>,
+
+    /// Energy needs and offers of the rooms, posted once per tick
+    /// Structure: room -> broker
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    #[serde(default)]
+    logistics: BTreeMap<WorldPosition, LogisticsBroker>,
+
+    /// Movement intents of the creeps, resolved after the creeps ran
+    /// Structure: room -> intents
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    #[serde(default)]
+    traffic: BTreeMap<WorldPosition, TrafficManager>,
+
+    /// Last runs and deferrals of the top level subsystems
+    #[serde(default)]
+    pub scheduler: SchedulerState,
+
+    /// CPU usage at which the running subsystem should stop, set by the scheduler
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    #[serde(default)]
+    pub cpu_deadline: Option<f32>,
+
+    /// Index of the first non critical creep to run in the next tick
+    #[serde(default)]
+    pub creep_offset: u32,
+
+    /// Records the behaviour tree of the current input, if sampled
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    #[serde(default)]
+    pub tracer: Option<Tracer>,
+
+    /// Behaviour tree traces sampled this tick
+    /// Structure: creep name or structure -> trace
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    #[serde(default)]
+    pub traces: BTreeMap<String, Vec<TraceEntry>>,
 }
 
 impl Clone for GameState {
@@ -54,10 +147,36 @@ impl Clone for GameState {
     }
 }
 
+impl Versioned for GameState {
+    fn migrations() -> &'static [Migration] {
+        &[migrate_legacy_creep_memory]
+    }
+
+    /// The large maps are kept in the shard segments
+    /// Returns None until both the main segment and the shards are loaded
+    fn load(segment: u32) -> Option<Self> {
+        let mut state: Self = segment::read_segment(segment)?;
+        if !state.load_shards(SHARD_SEGMENTS) {
+            return None;
+        }
+        Some(state)
+    }
+
+    fn encode_segments(&self, segment: u32) -> Result<Vec<(u32, String)>, SegmentError> {
+        let mut segments = vec![(segment, segment::encode(self)?)];
+        segments.extend(self.encode_shards(SHARD_SEGMENTS)?);
+        Ok(segments)
+    }
+}
+
 impl TaskInput for GameState {
     fn cpu_bucket(&self) -> Option<i16> {
         self.cpu_bucket
     }
+
+    fn tracer(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -92,17 +211,38 @@ impl Default for RoomIFF {
 }
 
 impl GameState {
-    pub fn read_from_segment_or_default(segment: u32) -> Pin<Box<Self>> {
-        let state = raw_memory::get_segment(segment)
-            .and_then(|string| {
-                serde_json::from_str(&string)
-                    .map_err(|e| {
-                        error!("Failed to deserialize game_state from segment {:?}", e);
-                    })
-                    .ok()
-            })
-            .unwrap_or_default();
-        Box::pin(state)
+    /// Clear the data only valid in a single tick
+    /// The GameState is kept on the heap between ticks
+    pub fn reset_tick_data(&mut self) {
+        self.cpu_bucket = None;
+        self.creep_count_by_room.clear();
+        self.creep_stats = Default::default();
+        self.cpu_deadline = None;
+        self.tracer = None;
+        self.traces.clear();
+    }
+
+    /// Load the maps kept in the shard segments
+    /// Returns false if the shards are not loaded in this tick
+    fn load_shards(&mut self, segments: Range<u32>) -> bool {
+        let shards = match shards::read_shards(segments) {
+            Some(shards) => shards,
+            None => return false,
+        };
+        // Maps read from the main segment before the shards were introduced are kept
+        self.scout_intel.extend(shards.scout_intel);
+        self.long_range_harvesters
+            .extend(shards.long_range_harvesters);
+        self.creep_memory.extend(shards.creep_memory);
+        true
+    }
+
+    fn encode_shards(&self, segments: Range<u32>) -> Result<Vec<(u32, String)>, SegmentError> {
+        let mut writer = ShardWriter::new(MAX_SHARD_LEN);
+        writer.push_map("scout_intel", &self.scout_intel)?;
+        writer.push_map("long_range_harvesters", &self.long_range_harvesters)?;
+        writer.push_map("creep_memory", &self.creep_memory)?;
+        shards::assign_shards(segments, writer.into_shards())
     }
 
     pub fn count_creeps_in_room<'a>(&'a mut self, room: &Room) -> &'a BTreeMap<Role, i8> {
@@ -118,47 +258,81 @@ impl GameState {
         self.creep_count_by_room.get(&pos).unwrap()
     }
 
-    /// Get an entry in the creep's memory
-    /// Inserts and empty map in the creep's name if none is found
-    pub fn creep_memory_entry(&mut self, name: CreepName) -> &mut CreepMemoryEntry {
-        self.creep_memory
-            .entry(name.0.to_owned())
-            .or_insert_with(|| Default::default())
+    /// Get the cached state of the room, refreshing it if it's stale
+    pub fn room_state<'a>(&'a mut self, room: &Room) -> &'a RoomState {
+        let pos = WorldPosition::from(room);
+        let time = screeps::game::time();
+        let stale = self
+            .rooms
+            .get(&pos)
+            .map(|state| time.wrapping_sub(state.refreshed_at) >= ROOM_STATE_INTERVAL)
+            .unwrap_or(true);
+        if stale {
+            let mut room_state = RoomState::read(room, time);
+            room_state.quotas = role_quotas(&room_state, pos, self);
+            self.rooms.insert(pos, room_state);
+        }
+        &self.rooms[&pos]
     }
 
-    pub fn creep_memory_get(&self, creep: CreepName) -> Option<&CreepMemoryEntry> {
-        self.creep_memory.get(creep.0)
+    pub fn spawn_queue(&mut self, room: WorldPosition) -> &mut SpawnQueue {
+        self.spawn_queues
+            .entry(room)
+            .or_insert_with(Default::default)
     }
 
-    #[allow(unused)]
-    pub fn creep_memory_set<T: Into<Value>>(&mut self, creep: CreepName, key: &str, value: T) {
-        let val: Value = value.into();
-        self.creep_memory_entry(creep).insert(key.to_owned(), val);
+    /// Get the logistics broker of the room, posting the entries of this tick if needed
+    pub fn logistics<'a>(&'a mut self, room: &Room) -> &'a mut LogisticsBroker {
+        let pos = WorldPosition::from(room);
+        let time = screeps::game::time();
+        let broker = self.logistics.entry(pos).or_insert_with(Default::default);
+        if broker.refreshed_at != time {
+            broker.begin(time);
+            logistics::post_entries(broker, room, &self.creep_memory);
+        }
+        broker
     }
 
-    #[allow(unused)]
-    pub fn creep_memory_bool(&self, creep: CreepName, key: &str) -> bool {
-        self.creep_memory_get(creep)
-            .and_then(|map| map.get(key))
-            .map(|x| x.as_bool().unwrap_or(false))
-            .unwrap_or(false)
+    pub fn traffic(&mut self, room: WorldPosition) -> &mut TrafficManager {
+        self.traffic.entry(room).or_insert_with(Default::default)
     }
 
-    pub fn creep_memory_string<'a>(&'a self, creep: CreepName, key: &str) -> Option<&'a str> {
-        self.creep_memory_get(creep)
-            .and_then(|map| map.get(key))
-            .and_then(|x| x.as_str())
+    /// The intents of the tick, leaving none behind
+    pub fn take_traffic(&mut self) -> BTreeMap<WorldPosition, TrafficManager> {
+        std::mem::take(&mut self.traffic)
     }
 
-    pub fn creep_memory_i64(&self, creep: CreepName, key: &str) -> Option<i64> {
-        self.creep_memory_get(creep)
-            .and_then(|map| map.get(key))
-            .and_then(|x| x.as_i64())
+    /// Creeps of the role at home in the room or queued to spawn in it
+    pub fn count_creeps_and_requests(&mut self, room: &Room, role: Role) -> i8 {
+        let pos = WorldPosition::from(room);
+        let alive = self
+            .count_creeps_in_room(room)
+            .get(&role)
+            .cloned()
+            .unwrap_or(0);
+        let queued = self.spawn_queue(pos).count(role).min(127) as i8;
+        alive + queued
     }
 
-    pub fn creep_memory_role(&self, creep: CreepName, key: &str) -> Option<Role> {
-        self.creep_memory_i64(creep, key)
-            .map(|x| Role::from(x as u8))
+    /// Count the creeps of the room again, after one is spawned
+    pub fn invalidate_creep_count(&mut self, room: &Room) {
+        self.creep_count_by_room.remove(&WorldPosition::from(room));
+    }
+
+    /// Get the creep's memory
+    /// Inserts an empty memory in the creep's name if none is found
+    pub fn creep_memory_entry(&mut self, name: CreepName) -> &mut CreepMemory {
+        self.creep_memory
+            .entry(name.0.to_owned())
+            .or_insert_with(|| Default::default())
+    }
+
+    pub fn creep_memory_get(&self, creep: CreepName) -> Option<&CreepMemory> {
+        self.creep_memory.get(creep.0)
+    }
+
+    pub fn creep_memory_role(&self, creep: CreepName) -> Option<Role> {
+        self.creep_memory_get(creep).and_then(|memory| memory.role)
     }
 
     pub fn cleanup_memory(&mut self) -> Result<(), Box<dyn Error>> {
@@ -179,6 +353,25 @@ impl GameState {
             debug!("cleaning up creep memory of dead creep {}", mem_name);
             self.creep_memory.remove(mem_name);
             screeps::memory::root().path_del(&format!("creeps.{}", mem_name));
+            // The reserved amounts are available again before the reservations expire
+            for broker in self.logistics.values_mut() {
+                broker.release(mem_name);
+            }
+        }
+
+        let visible_rooms = screeps::game::rooms::keys()
+            .into_iter()
+            .filter_map(|name| WorldPosition::parse_name(name.as_str()).ok())
+            .collect::<BTreeSet<_>>();
+        let lost_rooms = self
+            .rooms
+            .keys()
+            .filter(|pos| !visible_rooms.contains(*pos))
+            .cloned()
+            .collect::<Vec<_>>();
+        for pos in lost_rooms.iter() {
+            self.rooms.remove(pos);
+            self.logistics.remove(pos);
         }
 
         info!("Cleaned up memory");
@@ -186,25 +379,33 @@ impl GameState {
         Ok(())
     }
 
+    /// Dead creeps and creeps due for replacement are not counted
     fn count_roles_in_room(&self, room: &Room) -> BTreeMap<Role, i8> {
         let mut result = Role::all_roles()
             .into_iter()
             .map(|x| (x, 0))
             .collect::<BTreeMap<_, _>>();
+        let spawns = room.find(find::MY_SPAWNS);
+        // The replacement may wait for the next run of the spawns
+        let margin = self.config.spawn_skip;
 
         self.creep_memory
             .iter()
-            .filter(|(k, _v)| {
-                self.creep_memory_string(CreepName(k), HOME_ROOM)
-                    .map(|r| r == room.name())
+            .filter(|(_, memory)| {
+                memory
+                    .home
+                    .as_ref()
+                    .map(|r| *r == room.name())
                     .unwrap_or(false)
             })
-            .filter_map(|(k, _v)| {
-                self.creep_memory_i64(CreepName(k), CREEP_ROLE)
-                    .map(|x| x as u8)
+            .filter(|(name, _)| {
+                screeps::game::creeps::get(name.as_str())
+                    .map(|creep| !lifetime::is_dying(&creep, room, &spawns, margin))
+                    .unwrap_or(false)
             })
+            .filter_map(|(_, memory)| memory.role)
             .for_each(|role| {
-                if let Some(count) = result.get_mut(&Role::from(role)) {
+                if let Some(count) = result.get_mut(&role) {
                     *count += 1
                 } else {
                     error!(
@@ -0,0 +1,240 @@
+//! Broker matching the haulers to the energy needs and offers of a room
+//! Structures and creeps post entries every tick, haulers reserve an amount of an entry so
+//! other haulers do not chase the same target
+//!
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Reservations not completed in this many ticks are dropped
+pub const RESERVATION_TTL: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// The structure needs energy
+    Need,
+    /// The structure has energy to take
+    Offer,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogisticsEntry {
+    /// Energy not reserved by a hauler yet
+    pub amount: u32,
+    /// The higher the sooner
+    pub priority: i8,
+    /// Position in the room
+    pub pos: (u8, u8),
+}
+
+#[derive(Debug, Clone)]
+struct Reservation {
+    kind: EntryKind,
+    id: String,
+    amount: u32,
+    expires_at: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogisticsBroker {
+    /// Game time the entries were posted at
+    pub refreshed_at: u32,
+    needs: BTreeMap<String, LogisticsEntry>,
+    offers: BTreeMap<String, LogisticsEntry>,
+    /// Structure: creep name -> reservation
+    reservations: BTreeMap<String, Reservation>,
+}
+
+impl LogisticsBroker {
+    /// Clear the entries before posting the ones of this tick
+    /// Reservations are kept until they are completed or expire
+    pub fn begin(&mut self, time: u32) {
+        self.refreshed_at = time;
+        self.needs.clear();
+        self.offers.clear();
+        let expired = self
+            .reservations
+            .iter()
+            .filter(|(_, r)| r.expires_at <= time)
+            .map(|(creep, _)| creep.clone())
+            .collect::<Vec<_>>();
+        for creep in expired.iter() {
+            self.reservations.remove(creep);
+        }
+    }
+
+    /// Post an entry, the amounts already reserved on the structure are subtracted
+    pub fn post(&mut self, kind: EntryKind, id: &str, amount: u32, priority: i8, pos: (u8, u8)) {
+        let reserved = self
+            .reservations
+            .values()
+            .filter(|r| r.kind == kind && r.id == id)
+            .map(|r| r.amount)
+            .sum::<u32>();
+        let entry = LogisticsEntry {
+            amount: amount.saturating_sub(reserved),
+            priority,
+            pos,
+        };
+        self.entries_mut(kind).insert(id.to_owned(), entry);
+    }
+
+    #[cfg(test)]
+    pub fn entry(&self, kind: EntryKind, id: &str) -> Option<&LogisticsEntry> {
+        self.entries(kind).get(id)
+    }
+
+    /// Id of the structure reserved by the creep
+    pub fn reservation(&self, creep: &str) -> Option<&str> {
+        self.reservations.get(creep).map(|r| r.id.as_str())
+    }
+
+    /// Reserve `amount` of the entry of the highest priority in the range, the closest first
+    /// The current reservation of the creep is kept if it's of the same kind and range
+    pub fn reserve(
+        &mut self,
+        kind: EntryKind,
+        creep: &str,
+        amount: u32,
+        priorities: Range<i8>,
+        pos: (u8, u8),
+        time: u32,
+    ) -> Option<String> {
+        let current = self
+            .reservations
+            .get(creep)
+            .filter(|r| r.kind == kind)
+            .and_then(|r| self.entries(kind).get(&r.id).map(|e| (r.id.clone(), e)))
+            .filter(|(_, entry)| {
+                priorities.start <= entry.priority && entry.priority < priorities.end
+            })
+            .map(|(id, _)| id);
+        if current.is_some() {
+            return current;
+        }
+        self.release(creep);
+
+        let distance = |entry: &LogisticsEntry| {
+            let dx = (entry.pos.0 as i16 - pos.0 as i16).abs();
+            let dy = (entry.pos.1 as i16 - pos.1 as i16).abs();
+            dx.max(dy)
+        };
+        let id = self
+            .entries(kind)
+            .iter()
+            .filter(|(_, entry)| entry.amount > 0)
+            .filter(|(_, entry)| {
+                priorities.start <= entry.priority && entry.priority < priorities.end
+            })
+            .max_by_key(|(_, entry)| (entry.priority, -distance(entry)))
+            .map(|(id, _)| id.clone())?;
+
+        let entry = self.entries_mut(kind).get_mut(&id)?;
+        let amount = amount.min(entry.amount);
+        entry.amount -= amount;
+        self.reservations.insert(
+            creep.to_owned(),
+            Reservation {
+                kind,
+                id: id.clone(),
+                amount,
+                expires_at: time + RESERVATION_TTL,
+            },
+        );
+        Some(id)
+    }
+
+    /// The creep delivered or took the energy, the entry is updated by the next post
+    pub fn complete(&mut self, creep: &str) {
+        self.reservations.remove(creep);
+    }
+
+    /// The creep gave up on its reservation, the amount is available again
+    pub fn release(&mut self, creep: &str) {
+        if let Some(reservation) = self.reservations.remove(creep) {
+            if let Some(entry) = self.entries_mut(reservation.kind).get_mut(&reservation.id) {
+                entry.amount += reservation.amount;
+            }
+        }
+    }
+
+    fn entries(&self, kind: EntryKind) -> &BTreeMap<String, LogisticsEntry> {
+        match kind {
+            EntryKind::Need => &self.needs,
+            EntryKind::Offer => &self.offers,
+        }
+    }
+
+    fn entries_mut(&mut self, kind: EntryKind) -> &mut BTreeMap<String, LogisticsEntry> {
+        match kind {
+            EntryKind::Need => &mut self.needs,
+            EntryKind::Offer => &mut self.offers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haulers_do_not_double_book() {
+        let mut broker = LogisticsBroker::default();
+        broker.begin(1);
+        broker.post(EntryKind::Need, "extension", 50, 2, (10, 10));
+        broker.post(EntryKind::Need, "spawn", 300, 4, (30, 30));
+        broker.post(EntryKind::Need, "storage", 10_000, 0, (11, 11));
+
+        // The spawn comes first, however far it is
+        let first = broker.reserve(EntryKind::Need, "a", 300, 0..10, (10, 11), 1);
+        assert_eq!(first.as_deref(), Some("spawn"));
+        let second = broker.reserve(EntryKind::Need, "b", 100, 0..10, (10, 11), 1);
+        assert_eq!(second.as_deref(), Some("extension"));
+        // The extension is booked, the storage is the only entry left
+        let third = broker.reserve(EntryKind::Need, "c", 100, 0..10, (10, 11), 1);
+        assert_eq!(third.as_deref(), Some("storage"));
+        // Out of the range of priorities
+        assert!(broker
+            .reserve(EntryKind::Need, "d", 100, 1..2, (10, 11), 1)
+            .is_none());
+
+        // Reserving again keeps the reservation
+        let again = broker.reserve(EntryKind::Need, "a", 300, 0..10, (10, 11), 1);
+        assert_eq!(again, first);
+
+        // The reserved amounts are subtracted from the entries of the next tick
+        broker.begin(2);
+        broker.post(EntryKind::Need, "extension", 50, 2, (10, 10));
+        assert_eq!(
+            broker.entry(EntryKind::Need, "extension").unwrap().amount,
+            0
+        );
+    }
+
+    #[test]
+    fn test_released_amounts_are_available_again() {
+        let mut broker = LogisticsBroker::default();
+        broker.begin(1);
+        broker.post(EntryKind::Offer, "container", 500, 1, (5, 5));
+        broker.reserve(EntryKind::Offer, "a", 200, 0..10, (5, 6), 1);
+        assert_eq!(
+            broker.entry(EntryKind::Offer, "container").unwrap().amount,
+            300
+        );
+
+        broker.release("a");
+        assert_eq!(
+            broker.entry(EntryKind::Offer, "container").unwrap().amount,
+            500
+        );
+        assert!(broker.reservation("a").is_none());
+
+        // Expired reservations are dropped
+        broker.reserve(EntryKind::Offer, "b", 200, 0..10, (5, 6), 1);
+        broker.begin(1 + RESERVATION_TTL);
+        broker.post(EntryKind::Offer, "container", 500, 1, (5, 5));
+        assert_eq!(
+            broker.entry(EntryKind::Offer, "container").unwrap().amount,
+            500
+        );
+    }
+}
@@ -1,14 +1,31 @@
+pub mod codec;
 mod construction_state;
+mod creep_memory;
 mod creep_state;
 mod game_state;
+mod logistics;
+mod path_cache;
+mod room_state;
+mod routes;
+pub mod segment;
 mod sentinel;
+pub mod shards;
+mod spawn_queue;
+mod traffic;
 
 pub use self::construction_state::*;
+pub use self::creep_memory::*;
 pub use self::creep_state::*;
 pub use self::game_state::*;
+pub use self::logistics::*;
+pub use self::path_cache::*;
+pub use self::room_state::*;
+pub use self::routes::*;
 pub use self::sentinel::*;
+pub use self::spawn_queue::*;
+pub use self::traffic::*;
 
-use crate::bt::TaskInput;
+use crate::bt::{NodeTimers, TaskInput, Tracer, TreePath};
 
 pub struct WrappedState<'a, T, S: TaskInput> {
     pub item: T,
@@ -19,6 +36,18 @@ impl<'a, T, S: TaskInput> TaskInput for WrappedState<'a, T, S> {
     fn cpu_bucket(&self) -> Option<i16> {
         self.state.cpu_bucket()
     }
+
+    fn tree_path(&mut self) -> Option<&mut TreePath> {
+        self.state.tree_path()
+    }
+
+    fn node_timers(&mut self) -> Option<&mut NodeTimers> {
+        self.state.node_timers()
+    }
+
+    fn tracer(&mut self) -> Option<&mut Tracer> {
+        self.state.tracer()
+    }
 }
 
 impl<'a, T, S: TaskInput> WrappedState<'a, T, S> {
@@ -0,0 +1,195 @@
+//! Paths searched for the creeps, cached in their memory and walked one step per tick
+//! Steps are stored as the digits of the `Direction` constants, one character per step
+//!
+/// Ticks a creep able to move may stay in place before its path is searched again
+pub const STUCK_LIMIT: u8 = 2;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CachedPath {
+    /// Name of the room of the destination
+    #[serde(rename = "r")]
+    pub room: String,
+    #[serde(rename = "x")]
+    pub x: u8,
+    #[serde(rename = "y")]
+    pub y: u8,
+    /// Range to the destination the path stops at
+    #[serde(rename = "d")]
+    pub range: u8,
+
+    /// Directions left to walk
+    #[serde(rename = "s")]
+    pub steps: String,
+
+    /// Position the creep moved from in the last tick
+    #[serde(rename = "l")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last: Option<(u8, u8)>,
+
+    /// Ticks the creep did not move although it was not tired
+    #[serde(rename = "k")]
+    #[serde(default)]
+    pub stuck: u8,
+}
+
+impl CachedPath {
+    /// `path` are the positions returned by `PathFinder.search`, without the start
+    pub fn new(room: &str, x: u8, y: u8, range: u8, start: (u8, u8), path: &[(u8, u8)]) -> Self {
+        Self {
+            room: room.to_owned(),
+            x,
+            y,
+            range,
+            steps: encode_steps(start, path),
+            last: None,
+            stuck: 0,
+        }
+    }
+
+    pub fn leads_to(&self, room: &str, x: u8, y: u8, range: u8) -> bool {
+        self.room == room && self.x == x && self.y == y && self.range == range
+    }
+
+    /// Direction of the next step, None once the path is walked
+    pub fn next_direction(&self) -> Option<u8> {
+        self.steps
+            .chars()
+            .next()
+            .and_then(|c| c.to_digit(10))
+            .map(|d| d as u8)
+    }
+
+    /// The creep moved from `pos` this tick
+    pub fn moved(&mut self, pos: (u8, u8)) {
+        self.last = Some(pos);
+    }
+
+    /// Compare the position of the creep with the one it moved from
+    /// The step is walked if the creep arrived where it was heading, crossing to the next room
+    /// counts as arriving. A creep pushed elsewhere has left the path, its steps are dropped
+    pub fn update(&mut self, pos: (u8, u8), tired: bool) {
+        let last = match self.last.take() {
+            Some(last) => last,
+            None => return,
+        };
+        if pos == last {
+            if !tired {
+                self.stuck += 1;
+            }
+            return;
+        }
+        let expected = self.next_direction().and_then(|d| neighbour(last, d));
+        match expected {
+            Some(expected) if expected == pos || is_exit(expected) => {
+                self.steps.remove(0);
+                self.stuck = 0;
+            }
+            _ => {
+                self.steps.clear();
+                self.stuck = 0;
+            }
+        }
+    }
+
+    pub fn is_stuck(&self) -> bool {
+        self.stuck >= STUCK_LIMIT
+    }
+}
+
+/// Directions between the consecutive positions of the path
+/// Positions further than one tile apart are the two sides of an exit, the creep crosses
+/// those without moving
+fn encode_steps(start: (u8, u8), path: &[(u8, u8)]) -> String {
+    let mut steps = String::with_capacity(path.len());
+    let mut previous = start;
+    for pos in path {
+        if let Some(direction) = direction_to(previous, *pos) {
+            steps.push((b'0' + direction) as char);
+        }
+        previous = *pos;
+    }
+    steps
+}
+
+/// Offsets of the directions, `TOP` is 1 going clockwise to `TOP_LEFT`
+const OFFSETS: [(i8, i8); 8] = [
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+];
+
+/// Direction of the step between the adjacent tiles
+pub fn direction_to(from: (u8, u8), to: (u8, u8)) -> Option<u8> {
+    let dx = to.0 as i16 - from.0 as i16;
+    let dy = to.1 as i16 - from.1 as i16;
+    OFFSETS
+        .iter()
+        .position(|(x, y)| *x as i16 == dx && *y as i16 == dy)
+        .map(|i| i as u8 + 1)
+}
+
+/// Tile one step in the direction, None outside of the room
+pub fn neighbour(pos: (u8, u8), direction: u8) -> Option<(u8, u8)> {
+    let (dx, dy) = OFFSETS.get(direction.checked_sub(1)? as usize)?;
+    let x = pos.0 as i16 + *dx as i16;
+    let y = pos.1 as i16 + *dy as i16;
+    if x < 0 || y < 0 || x > 49 || y > 49 {
+        return None;
+    }
+    Some((x as u8, y as u8))
+}
+
+pub fn is_exit(pos: (u8, u8)) -> bool {
+    pos.0 == 0 || pos.1 == 0 || pos.0 == 49 || pos.1 == 49
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps_skip_exits() {
+        // Right to the exit, then appearing on the left side of the next room
+        let path = CachedPath::new(
+            "W1N1",
+            2,
+            10,
+            1,
+            (47, 10),
+            &[(48, 10), (49, 11), (0, 11), (1, 11)],
+        );
+        assert_eq!(path.steps, "343");
+        assert_eq!(path.next_direction(), Some(3));
+    }
+
+    #[test]
+    fn test_walking_and_getting_stuck() {
+        let mut path = CachedPath::new("W1N1", 20, 20, 1, (10, 10), &[(11, 10), (12, 11)]);
+
+        path.moved((10, 10));
+        path.update((11, 10), false);
+        assert_eq!(path.next_direction(), Some(4));
+
+        // Tired creeps are not stuck
+        path.moved((11, 10));
+        path.update((11, 10), true);
+        assert!(!path.is_stuck());
+        for _ in 0..STUCK_LIMIT {
+            path.moved((11, 10));
+            path.update((11, 10), false);
+        }
+        assert!(path.is_stuck());
+
+        // Pushed off the path
+        path.moved((11, 10));
+        path.update((10, 11), false);
+        assert_eq!(path.next_direction(), None);
+        assert!(!path.is_stuck());
+    }
+}
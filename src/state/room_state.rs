@@ -0,0 +1,143 @@
+//! Cached state of the rooms, read from the game every few ticks
+//!
+use super::routes::{Destination, ResourceRoutes};
+use crate::creeps::Role;
+use screeps::{
+    constants::find,
+    objects::{HasStore, OwnedStructureProperties, Room, StructureContainer},
+    prelude::*,
+    Part,
+};
+use std::collections::BTreeMap;
+use stdweb::unstable::TryInto;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThreatLevel {
+    None,
+    /// Hostile creeps without attack parts, e.g. scouts
+    Low,
+    /// Hostile creeps able to attack
+    High,
+}
+
+impl Default for ThreatLevel {
+    fn default() -> Self {
+        ThreatLevel::None
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RoomState {
+    /// Game time of the last refresh
+    pub refreshed_at: u32,
+    pub my: bool,
+    pub level: u8,
+
+    pub n_sources: i8,
+    pub n_containers: i8,
+    /// Containers next to a source, mined by the miners
+    pub n_source_containers: i8,
+    pub n_construction_sites: i8,
+    pub has_storage: bool,
+
+    /// Energy the spawns and extensions can hold
+    pub energy_capacity: u32,
+    pub energy_in_containers: u32,
+
+    pub threat: ThreatLevel,
+
+    /// Destinations of the resources other than energy
+    pub routes: ResourceRoutes,
+
+    /// Number of creeps of each role the room should have
+    pub quotas: BTreeMap<Role, i8>,
+}
+
+impl RoomState {
+    /// Read the inventories of the room
+    /// The quotas are left empty
+    pub fn read(room: &Room, time: u32) -> Self {
+        let controller = room.controller();
+        let containers = js! {
+            const room = @{room};
+            return room.find(FIND_STRUCTURES, {
+                filter: (s) => s.structureType == STRUCTURE_CONTAINER
+            });
+        };
+        let containers: Vec<StructureContainer> = containers.try_into().unwrap_or_else(|e| {
+            error!("Failed to convert containers {:?}", e);
+            vec![]
+        });
+        let sources = room.find(find::SOURCES);
+        let n_source_containers = containers
+            .iter()
+            .filter(|container| {
+                sources
+                    .iter()
+                    .any(|source| source.pos().is_near_to(*container))
+            })
+            .count();
+        let storage = room.storage();
+        let hostiles = room.find(find::HOSTILE_CREEPS);
+        let threat = if hostiles.is_empty() {
+            ThreatLevel::None
+        } else if hostiles.iter().any(|creep| {
+            creep.get_active_bodyparts(Part::Attack) > 0
+                || creep.get_active_bodyparts(Part::RangedAttack) > 0
+        }) {
+            ThreatLevel::High
+        } else {
+            ThreatLevel::Low
+        };
+
+        Self {
+            refreshed_at: time,
+            my: controller.as_ref().map(|c| c.my()).unwrap_or(false),
+            level: controller.as_ref().map(|c| c.level() as u8).unwrap_or(0),
+            n_sources: sources.len().min(127) as i8,
+            n_containers: containers.len().min(127) as i8,
+            n_source_containers: n_source_containers.min(127) as i8,
+            n_construction_sites: room.find(find::CONSTRUCTION_SITES).len().min(127) as i8,
+            has_storage: storage.is_some(),
+            energy_capacity: room.energy_capacity_available(),
+            energy_in_containers: containers.iter().map(|c| c.energy()).sum(),
+            threat,
+            routes: read_routes(room),
+            quotas: BTreeMap::new(),
+        }
+    }
+
+    pub fn quota(&self, role: Role) -> i8 {
+        self.quotas.get(&role).cloned().unwrap_or(0)
+    }
+}
+
+/// Destinations built in the room
+fn read_routes(room: &Room) -> ResourceRoutes {
+    let destinations = [
+        Destination::Storage,
+        Destination::Terminal,
+        Destination::Lab,
+        Destination::Factory,
+    ];
+    let structure_types = destinations
+        .iter()
+        .map(|destination| destination.structure_type())
+        .collect::<Vec<_>>();
+    let built = js! {
+        const types = @{room}.find(FIND_MY_STRUCTURES).map((s) => s.structureType);
+        return @{structure_types}.map((t) => types.includes(t));
+    };
+    let built: Vec<bool> = built.try_into().unwrap_or_else(|e| {
+        error!("Failed to convert built destinations {:?}", e);
+        vec![]
+    });
+    ResourceRoutes::new(
+        destinations
+            .iter()
+            .zip(built)
+            .filter(|(_, built)| *built)
+            .map(|(destination, _)| *destination)
+            .collect(),
+    )
+}
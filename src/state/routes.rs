@@ -0,0 +1,105 @@
+//! Where the haulers take the resources other than energy
+//! Energy is matched to the structures by the logistics broker
+//!
+use screeps::ResourceType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Storage,
+    Terminal,
+    Lab,
+    Factory,
+}
+
+impl Destination {
+    /// Value of the `STRUCTURE_*` constant
+    pub fn structure_type(self) -> &'static str {
+        match self {
+            Destination::Storage => "storage",
+            Destination::Terminal => "terminal",
+            Destination::Lab => "lab",
+            Destination::Factory => "factory",
+        }
+    }
+}
+
+const BASE_MINERALS: &[ResourceType] = &[
+    ResourceType::Hydrogen,
+    ResourceType::Oxygen,
+    ResourceType::Utrium,
+    ResourceType::Lemergium,
+    ResourceType::Keanium,
+    ResourceType::Zynthium,
+    ResourceType::Catalyst,
+];
+
+/// Destinations of the resource in order of preference
+/// Compounds are boosts for the labs, the rest are commodities for the factory
+/// Labs and factories reject the resources they can not hold, so the next destination is used
+fn preferred(resource: ResourceType) -> &'static [Destination] {
+    use self::Destination::*;
+    if resource == ResourceType::Energy || resource == ResourceType::Power {
+        &[Storage]
+    } else if BASE_MINERALS.contains(&resource) {
+        &[Terminal, Storage]
+    } else {
+        &[Lab, Factory, Terminal, Storage]
+    }
+}
+
+/// Routing table of a room, only routes to the destinations built in the room
+#[derive(Debug, Clone, Default)]
+pub struct ResourceRoutes {
+    present: Vec<Destination>,
+}
+
+impl ResourceRoutes {
+    pub fn new(present: Vec<Destination>) -> Self {
+        Self { present }
+    }
+
+    /// Destinations of the resource in the room, in order of preference
+    /// Empty if the resource has nowhere to go, it's left where it is
+    pub fn route(&self, resource: ResourceType) -> Vec<Destination> {
+        preferred(resource)
+            .iter()
+            .filter(|destination| self.present.contains(destination))
+            .cloned()
+            .collect()
+    }
+
+    pub fn is_routed(&self, resource: ResourceType) -> bool {
+        !self.route(resource).is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routes_skip_missing_destinations() {
+        let routes = ResourceRoutes::new(vec![Destination::Storage, Destination::Lab]);
+        assert_eq!(
+            routes.route(ResourceType::Hydrogen),
+            vec![Destination::Storage]
+        );
+        assert_eq!(
+            routes.route(ResourceType::UtriumHydride),
+            vec![Destination::Lab, Destination::Storage]
+        );
+
+        let routes = ResourceRoutes::new(vec![Destination::Terminal, Destination::Storage]);
+        assert_eq!(
+            routes.route(ResourceType::Keanium),
+            vec![Destination::Terminal, Destination::Storage]
+        );
+    }
+
+    #[test]
+    fn test_nothing_is_routed_without_destinations() {
+        let routes = ResourceRoutes::default();
+        assert!(!routes.is_routed(ResourceType::Power));
+        assert!(!routes.is_routed(ResourceType::Catalyst));
+    }
+}
@@ -0,0 +1,359 @@
+//! Versioned format of the memory segments
+//! Segments start with a `#<version>#` header followed by the JSON data
+//! or a `#<version>b#` header followed by the binary data
+//! Segments written before the header was introduced are version 0
+//!
+use super::codec::{self, Codec};
+use crate::{BACKUP_SEGMENT_OFFSET, MAX_BACKUPS_PER_TICK};
+use bincode;
+use screeps::{game, raw_memory};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{self, Value};
+use std::cell::Cell;
+use std::fmt::{self, Display, Formatter};
+
+thread_local! {
+    /// Tick and number of the backups written in it
+    static BACKUPS: Cell<(u32, u32)> = const { Cell::new((0, 0)) };
+}
+
+/// Convert the data of a version to the next one
+pub type Migration = fn(Value) -> Result<Value, String>;
+
+/// Data stored in a versioned memory segment
+pub trait Versioned: Serialize + DeserializeOwned + Default {
+    /// `migrations()[n]` converts version `n` to version `n + 1`
+    /// Append a migration on every breaking change of the schema
+    fn migrations() -> &'static [Migration];
+
+    fn version() -> u32 {
+        Self::migrations().len() as u32
+    }
+
+    /// Encoding used when writing the item, data is read in any encoding
+    fn codec() -> Codec {
+        Codec::Json
+    }
+
+    /// Convert binary data of an older version to the JSON value of that version, which is
+    /// then migrated. Binary data is not self describing, so it is read with the schema of its
+    /// version, see `binary_value`
+    /// Types using the binary codec keep the previous schema here on every breaking change
+    fn binary_to_value(version: u32, _data: &str) -> Result<Value, SegmentError> {
+        Err(SegmentError::Migration {
+            version,
+            error: "no schema to read the binary data".to_owned(),
+        })
+    }
+
+    /// Read the item from `segment`, None if it's not loaded in this tick
+    /// Override to keep parts of the item in other segments
+    fn load(segment: u32) -> Option<Self> {
+        read_segment(segment)
+    }
+
+    /// Encode the item into the segments it's written to, starting with `segment`
+    /// Override to keep parts of the item in other segments
+    fn encode_segments(&self, segment: u32) -> Result<Vec<(u32, String)>, SegmentError> {
+        Ok(vec![(segment, encode(self)?)])
+    }
+
+    /// Write the encoded segments
+    fn write(segments: &[(u32, String)]) {
+        for (segment, data) in segments.iter() {
+            raw_memory::set_segment(*segment, data.as_str());
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SegmentError {
+    Header(String),
+    /// Written by a newer version of the code
+    UnknownVersion(u32),
+    Migration {
+        version: u32,
+        error: String,
+    },
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+    /// The data does not fit in its segments
+    Overflow(String),
+}
+
+impl Display for SegmentError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SegmentError::Header(header) => write!(f, "invalid header {:?}", header),
+            SegmentError::UnknownVersion(version) => write!(f, "unknown version {}", version),
+            SegmentError::Migration { version, error } => {
+                write!(f, "failed to migrate version {}: {}", version, error)
+            }
+            SegmentError::Json(e) => write!(f, "invalid json: {}", e),
+            SegmentError::Binary(e) => write!(f, "invalid binary data: {}", e),
+            SegmentError::Overflow(e) => write!(f, "data too large: {}", e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for SegmentError {
+    fn from(e: serde_json::Error) -> Self {
+        SegmentError::Json(e)
+    }
+}
+
+impl From<bincode::Error> for SegmentError {
+    fn from(e: bincode::Error) -> Self {
+        SegmentError::Binary(e)
+    }
+}
+
+pub fn encode<T: Versioned>(item: &T) -> Result<String, SegmentError> {
+    match T::codec() {
+        Codec::Json => {
+            let data = serde_json::to_string(item)?;
+            Ok(with_header::<T>(data.as_str()))
+        }
+        Codec::Binary => {
+            let data = codec::to_binary(item)?;
+            Ok(format!("#{}b#{}", T::version(), data))
+        }
+    }
+}
+
+/// Prefix JSON serialized data of `T` with the header
+pub fn with_header<T: Versioned>(data: &str) -> String {
+    format!("#{}#{}", T::version(), data)
+}
+
+pub fn decode<T: Versioned>(raw: &str) -> Result<T, SegmentError> {
+    let (version, codec, data) = split_header(raw)?;
+    let current = T::version();
+    if version > current {
+        Err(SegmentError::UnknownVersion(version))?;
+    }
+    let mut value = match codec {
+        Codec::Binary if version == current => return Ok(codec::from_binary(data)?),
+        Codec::Binary => T::binary_to_value(version, data)?,
+        Codec::Json if version == current => return Ok(serde_json::from_str(data)?),
+        Codec::Json => serde_json::from_str(data)?,
+    };
+
+    info!(
+        "Migrating segment data from version {} to {}",
+        version, current
+    );
+    for (v, migration) in T::migrations().iter().enumerate().skip(version as usize) {
+        value = migration(value).map_err(|error| SegmentError::Migration {
+            version: v as u32,
+            error,
+        })?;
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Read binary data with the schema `S` of an older version, see `Versioned::binary_to_value`
+/// No binary segment has an older version yet
+#[allow(dead_code)]
+pub fn binary_value<S: Serialize + DeserializeOwned>(data: &str) -> Result<Value, SegmentError> {
+    let item: S = codec::from_binary(data)?;
+    Ok(serde_json::to_value(item)?)
+}
+
+/// Read the data of a segment, None if it's not loaded in this tick
+/// If it fails to load the raw segment is kept in its backup slot and the default is returned
+pub fn read_segment<T: Versioned>(segment: u32) -> Option<T> {
+    let raw = raw_memory::get_segment(segment)?;
+    if raw.is_empty() {
+        return Some(T::default());
+    }
+    let result = decode(raw.as_str()).unwrap_or_else(|e| {
+        let backup = segment + BACKUP_SEGMENT_OFFSET;
+        if reserve_backup(game::time()) {
+            error!(
+                "Failed to load segment {}, {}. Keeping its data in segment {}",
+                segment, e, backup
+            );
+            raw_memory::set_segment(backup, raw.as_str());
+        } else {
+            error!(
+                "Failed to load segment {}, {}. Too many backups in this tick, dropping its data",
+                segment, e
+            );
+        }
+        T::default()
+    });
+    Some(result)
+}
+
+/// Count a backup written in this tick, false if the segments left for the backups are used up
+fn reserve_backup(time: u32) -> bool {
+    BACKUPS.with(|backups| {
+        let (tick, count) = backups.get();
+        let count = if tick == time { count } else { 0 };
+        if count >= MAX_BACKUPS_PER_TICK {
+            return false;
+        }
+        backups.set((time, count + 1));
+        true
+    })
+}
+
+/// Split the version and the encoding from the data
+fn split_header(raw: &str) -> Result<(u32, Codec, &str), SegmentError> {
+    if !raw.starts_with('#') {
+        return Ok((0, Codec::Json, raw));
+    }
+    let end = raw[1..]
+        .find('#')
+        .map(|i| i + 1)
+        .ok_or_else(|| SegmentError::Header(raw.chars().take(16).collect()))?;
+    let header = &raw[1..end];
+    let (version, codec) = match header.strip_suffix('b') {
+        Some(version) => (version, Codec::Binary),
+        None => (header, Codec::Json),
+    };
+    let version = version
+        .parse()
+        .map_err(|_| SegmentError::Header(raw[..end].to_owned()))?;
+    Ok((version, codec, &raw[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    struct Data {
+        count: u32,
+        name: String,
+    }
+
+    impl Versioned for Data {
+        fn migrations() -> &'static [Migration] {
+            &[rename_key, fail_on_negative]
+        }
+    }
+
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    struct Packed {
+        rooms: Vec<(String, u32)>,
+    }
+
+    /// Schema of `Packed` in version 0
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    struct PackedV0 {
+        names: Vec<String>,
+    }
+
+    impl Versioned for Packed {
+        fn migrations() -> &'static [Migration] {
+            &[names_to_rooms]
+        }
+
+        fn codec() -> Codec {
+            Codec::Binary
+        }
+
+        fn binary_to_value(version: u32, data: &str) -> Result<Value, SegmentError> {
+            match version {
+                0 => binary_value::<PackedV0>(data),
+                _ => Err(SegmentError::UnknownVersion(version)),
+            }
+        }
+    }
+
+    fn names_to_rooms(value: Value) -> Result<Value, String> {
+        let names = value["names"].as_array().ok_or("missing key names")?;
+        let rooms = names
+            .iter()
+            .map(|name| serde_json::json!([name, 0]))
+            .collect::<Vec<_>>();
+        Ok(serde_json::json!({ "rooms": rooms }))
+    }
+
+    fn rename_key(mut value: Value) -> Result<Value, String> {
+        let count = value
+            .as_object_mut()
+            .and_then(|o| o.remove("n"))
+            .ok_or("missing key n")?;
+        value["count"] = count;
+        Ok(value)
+    }
+
+    fn fail_on_negative(value: Value) -> Result<Value, String> {
+        if value["count"].as_i64().unwrap_or(0) < 0 {
+            Err("negative count".to_owned())?;
+        }
+        Ok(value)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = Data {
+            count: 3,
+            name: "W1N1".to_owned(),
+        };
+        let raw = encode(&data).unwrap();
+        assert_eq!(raw, r##"#2#{"count":3,"name":"W1N1"}"##);
+        assert_eq!(decode::<Data>(&raw).unwrap(), data);
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let data = Packed {
+            rooms: vec![("W1N1".to_owned(), 3), ("W2N1".to_owned(), 1 << 20)],
+        };
+        let raw = encode(&data).unwrap();
+        assert!(raw.starts_with("#1b#"));
+        assert_eq!(decode::<Packed>(&raw).unwrap(), data);
+
+        let data: Packed = decode(r##"#1#{"rooms":[["W1N1",3]]}"##).unwrap();
+        assert_eq!(data.rooms, vec![("W1N1".to_owned(), 3)]);
+    }
+
+    #[test]
+    fn test_binary_data_is_migrated() {
+        let old = PackedV0 {
+            names: vec!["W1N1".to_owned(), "W2N1".to_owned()],
+        };
+        let raw = format!("#0b#{}", codec::to_binary(&old).unwrap());
+
+        let data: Packed = decode(&raw).unwrap();
+        assert_eq!(
+            data.rooms,
+            vec![("W1N1".to_owned(), 0), ("W2N1".to_owned(), 0)]
+        );
+
+        match decode::<Data>(&raw) {
+            Err(SegmentError::Migration { version: 0, .. }) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_unversioned_data_is_migrated() {
+        let data: Data = decode(r#"{"n":5,"name":"W1N1"}"#).unwrap();
+        assert_eq!(data.count, 5);
+
+        let data: Data = decode(r##"#1#{"count":6,"name":"W1N1"}"##).unwrap();
+        assert_eq!(data.count, 6);
+    }
+
+    #[test]
+    fn test_failures_are_reported() {
+        match decode::<Data>(r#"{"n":-1,"name":"W1N1"}"#) {
+            Err(SegmentError::Migration { version: 1, .. }) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        match decode::<Data>(r##"#3#{}"##) {
+            Err(SegmentError::UnknownVersion(3)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        match decode::<Data>(r##"#x#{}"##) {
+            Err(SegmentError::Header(_)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+}
@@ -1,45 +1,177 @@
-use screeps::raw_memory;
-use serde::Serialize;
-use std::marker::PhantomData;
-use std::ptr::NonNull;
+//! Keep the data of a segment on the heap between ticks
+//! The heap survives until the VM is reset, so the segment is only read after a reset
+//!
+use super::segment::Versioned;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, DerefMut};
 
-pub struct MemorySentinel<'a, T: Serialize> {
-    pub memory_segment: u8,
-    pub save_to_memory: bool,
-
-    item: NonNull<T>,
+/// Bookkeeping of writing a cached item to its segment
+struct SaveState {
+    segment: u32,
+    /// Write the item at most once every `save_interval` ticks
+    save_interval: u32,
+    /// Hash of the encoded segments last read or written, unchanged items are not written
+    saved_hash: Option<u64>,
+    last_save: u32,
+}
 
-    _m: PhantomData<&'a i8>,
+pub struct SegmentCache<T: Versioned> {
+    item: Option<T>,
+    save: SaveState,
 }
 
-impl<'a, T: Serialize> MemorySentinel<'a, T> {
-    pub fn new(memory_segment: u8, item: &T) -> Self {
+impl<T: Versioned> SegmentCache<T> {
+    pub fn new(segment: u32, save_interval: u32) -> Self {
         Self {
-            item: NonNull::from(item),
-            memory_segment,
-            save_to_memory: true,
-            _m: PhantomData,
+            item: None,
+            save: SaveState {
+                segment,
+                save_interval,
+                saved_hash: None,
+                last_save: 0,
+            },
+        }
+    }
+
+    /// Borrow the cached item, reading it from its segment after a reset
+    /// Returns None if the segment is not loaded in this tick
+    pub fn load<'a>(&'a mut self, time: u32) -> Option<MemorySentinel<'a, T>> {
+        if self.item.is_none() {
+            let item = T::load(self.save.segment)?;
+            self.save.saved_hash = item
+                .encode_segments(self.save.segment)
+                .ok()
+                .map(|s| hash(&s));
+            self.save.last_save = time;
+            self.item = Some(item);
         }
+        let item = match self.item {
+            Some(ref mut item) => item,
+            None => return None,
+        };
+        Some(MemorySentinel {
+            save_to_memory: true,
+            time,
+            item,
+            save: &mut self.save,
+        })
     }
 }
 
-impl<'a, T: Serialize> Drop for MemorySentinel<'a, T> {
+/// Borrows a cached item, writing it to its segment when dropped if its encoding changed
+/// The item is only reachable through the sentinel while it's borrowed
+pub struct MemorySentinel<'a, T: Versioned> {
+    pub save_to_memory: bool,
+
+    time: u32,
+    item: &'a mut T,
+    save: &'a mut SaveState,
+}
+
+impl<'a, T: Versioned> Deref for MemorySentinel<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &*self.item
+    }
+}
+
+impl<'a, T: Versioned> DerefMut for MemorySentinel<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut *self.item
+    }
+}
+
+impl<'a, T: Versioned> Drop for MemorySentinel<'a, T> {
     fn drop(&mut self) {
-        if !self.save_to_memory {
+        let save = &mut self.save;
+        if !self.save_to_memory || self.time.wrapping_sub(save.last_save) < save.save_interval {
             return;
         }
-        let segment = self.memory_segment;
-
-        unsafe {
-            match serde_json::to_string(self.item.as_ref()) {
-                Ok(data) => {
-                    raw_memory::set_segment(segment as u32, data.as_str());
-                }
-                Err(e) => {
-                    error!("Failed to serialize {:?}", e);
-                }
+        save.last_save = self.time;
+        let segments = match self.item.encode_segments(save.segment) {
+            Ok(segments) => segments,
+            Err(e) => {
+                error!(
+                    "Failed to save segment {}, {}. Keeping the previous save",
+                    save.segment, e
+                );
+                return;
             }
+        };
+        let hash = hash(&segments);
+        if save.saved_hash != Some(hash) {
+            T::write(&segments);
+            save.saved_hash = Some(hash);
         }
     }
 }
 
+fn hash(segments: &[(u32, String)]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    segments.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::segment::Migration;
+    use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        static SAVES: Cell<u32> = const { Cell::new(0) };
+    }
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct Counter {
+        value: u32,
+    }
+
+    impl Versioned for Counter {
+        fn migrations() -> &'static [Migration] {
+            &[]
+        }
+
+        fn load(_segment: u32) -> Option<Self> {
+            Some(Counter::default())
+        }
+
+        fn write(_segments: &[(u32, String)]) {
+            SAVES.with(|saves| saves.set(saves.get() + 1));
+        }
+    }
+
+    fn saves() -> u32 {
+        SAVES.with(|saves| saves.get())
+    }
+
+    #[test]
+    fn test_only_modified_items_are_saved() {
+        let mut cache = SegmentCache::<Counter>::new(0, 2);
+
+        assert_eq!(cache.load(1).unwrap().value, 0);
+        assert_eq!(saves(), 0);
+
+        cache.load(2).unwrap().value += 1;
+        assert_eq!(saves(), 0, "Saved before the interval elapsed");
+
+        assert_eq!(cache.load(3).unwrap().value, 1);
+        assert_eq!(saves(), 1);
+
+        assert_eq!(cache.load(6).unwrap().value, 1);
+        assert_eq!(saves(), 1, "Saved an unmodified item");
+
+        // The game loop always borrows the state mutably
+        {
+            let mut item = cache.load(9).unwrap();
+            let counter: &mut Counter = &mut item;
+            counter.value = 1;
+        }
+        assert_eq!(saves(), 1, "Saved an item borrowed mutably but unchanged");
+
+        cache.load(12).unwrap().value = 2;
+        assert_eq!(saves(), 2);
+    }
+}
@@ -0,0 +1,245 @@
+//! Split the large maps of the GameState across a range of segments
+//! Each shard is a JSON object of sections, a section holding a part of one map
+//!
+use super::segment::{self, Migration, SegmentError, Versioned};
+use super::{deserialize_creep_memories, CreepMemory, ScoutInfo};
+use crate::rooms::WorldPosition;
+use serde::Serialize;
+use serde_json;
+use std::collections::BTreeMap;
+use std::mem;
+use std::ops::Range;
+
+/// Screeps limits segments to 100 KB, leave some room for the header
+pub const MAX_SHARD_LEN: usize = 100 * 1000;
+
+/// Maps of the GameState read from the shards
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Shards {
+    #[serde(default)]
+    pub scout_intel: BTreeMap<WorldPosition, ScoutInfo>,
+
+    #[serde(default)]
+    pub long_range_harvesters: BTreeMap<WorldPosition, [u8; 4]>,
+
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_creep_memories")]
+    pub creep_memory: BTreeMap<String, CreepMemory>,
+}
+
+impl Versioned for Shards {
+    fn migrations() -> &'static [Migration] {
+        &[]
+    }
+}
+
+impl Shards {
+    fn merge(&mut self, other: Shards) {
+        self.scout_intel.extend(other.scout_intel);
+        self.long_range_harvesters
+            .extend(other.long_range_harvesters);
+        self.creep_memory.extend(other.creep_memory);
+    }
+}
+
+/// Read and merge the shards in `segments`
+/// Returns None if any of the segments is not loaded in this tick
+pub fn read_shards(segments: Range<u32>) -> Option<Shards> {
+    let mut result = Shards::default();
+    for id in segments {
+        let shard = segment::read_segment::<Shards>(id)?;
+        result.merge(shard);
+    }
+    Some(result)
+}
+
+/// Pair the shards with `segments`, clearing the segments left unused
+/// Fails if the shards do not fit in the segments, so the previous save is kept
+pub fn assign_shards(
+    segments: Range<u32>,
+    shards: Vec<String>,
+) -> Result<Vec<(u32, String)>, SegmentError> {
+    let n_segments = segments.len();
+    if shards.len() > n_segments {
+        Err(SegmentError::Overflow(format!(
+            "{} shards do not fit in {} segments",
+            shards.len(),
+            n_segments
+        )))?;
+    }
+    let mut shards = shards.into_iter();
+    let result = segments
+        .map(|id| {
+            let data = shards
+                .next()
+                .map(|shard| segment::with_header::<Shards>(shard.as_str()))
+                .unwrap_or_default();
+            (id, data)
+        })
+        .collect();
+    Ok(result)
+}
+
+/// Packs the entries of maps into JSON objects no longer than `max_len`
+/// Keys have to serialize as strings
+pub struct ShardWriter {
+    max_len: usize,
+    shards: Vec<String>,
+    current: String,
+    section: Option<&'static str>,
+}
+
+impl ShardWriter {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            shards: vec![],
+            current: String::new(),
+            section: None,
+        }
+    }
+
+    pub fn push_map<K: Serialize, V: Serialize>(
+        &mut self,
+        section: &'static str,
+        map: &BTreeMap<K, V>,
+    ) -> Result<(), SegmentError> {
+        for (key, value) in map.iter() {
+            let key = serde_json::to_string(key)?;
+            let value = serde_json::to_string(value)?;
+            self.push_entry(section, key.as_str(), value.as_str())?;
+        }
+        Ok(())
+    }
+
+    pub fn into_shards(mut self) -> Vec<String> {
+        self.finish_shard();
+        self.shards
+    }
+
+    /// Entries longer than a shard are rejected
+    fn push_entry(
+        &mut self,
+        section: &'static str,
+        key: &str,
+        value: &str,
+    ) -> Result<(), SegmentError> {
+        // `{"section":{key:value}}` in the worst case
+        let len = section.len() + key.len() + value.len() + 8;
+        if len > self.max_len {
+            Err(SegmentError::Overflow(format!(
+                "entry {} of {} is {} bytes long",
+                key, section, len
+            )))?;
+        }
+        if !self.current.is_empty() && self.current.len() + len > self.max_len {
+            self.finish_shard();
+        }
+        if self.current.is_empty() {
+            self.current.push('{');
+        }
+        if self.section != Some(section) {
+            if self.section.is_some() {
+                self.current.push_str("},");
+            }
+            self.current.push('"');
+            self.current.push_str(section);
+            self.current.push_str("\":{");
+            self.section = Some(section);
+        } else {
+            self.current.push(',');
+        }
+        self.current.push_str(key);
+        self.current.push(':');
+        self.current.push_str(value);
+        Ok(())
+    }
+
+    fn finish_shard(&mut self) {
+        if self.current.is_empty() {
+            return;
+        }
+        if self.section.take().is_some() {
+            self.current.push('}');
+        }
+        self.current.push('}');
+        let shard = mem::take(&mut self.current);
+        self.shards.push(shard);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_maps_are_split_by_size() {
+        let names = (0..20)
+            .map(|i| (format!("creep_{}", i), i))
+            .collect::<BTreeMap<_, _>>();
+        let rooms = (0..20)
+            .map(|i| (format!("W{}N1", i), vec![i; 3]))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut writer = ShardWriter::new(128);
+        writer.push_map("names", &names).unwrap();
+        writer.push_map("rooms", &rooms).unwrap();
+        let shards = writer.into_shards();
+
+        assert!(shards.len() > 1);
+        let mut n_names = 0;
+        let mut n_rooms = 0;
+        for shard in shards.iter() {
+            assert!(shard.len() <= 128, "{} is too long", shard);
+            let value: Value = serde_json::from_str(shard).unwrap();
+            n_names += value
+                .get("names")
+                .and_then(|v| v.as_object())
+                .map(|o| o.len())
+                .unwrap_or(0);
+            n_rooms += value
+                .get("rooms")
+                .and_then(|v| v.as_object())
+                .map(|o| o.len())
+                .unwrap_or(0);
+        }
+        assert_eq!(n_names, 20);
+        assert_eq!(n_rooms, 20);
+    }
+
+    #[test]
+    fn test_oversize_entries_are_rejected() {
+        let mut map = BTreeMap::new();
+        map.insert("small".to_owned(), "a".to_owned());
+        map.insert("xlarge".to_owned(), "a".repeat(200));
+
+        let mut writer = ShardWriter::new(128);
+        match writer.push_map("names", &map) {
+            Err(SegmentError::Overflow(_)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_shards_must_fit_in_the_segments() {
+        let shards = vec!["{}".to_owned(), "{}".to_owned()];
+        let segments = assign_shards(4..7, shards.clone()).unwrap();
+        assert_eq!(
+            segments.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![4, 5, 6]
+        );
+        assert_eq!(segments[2].1, "");
+
+        match assign_shards(4..5, shards) {
+            Err(SegmentError::Overflow(_)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_empty_writer_has_no_shards() {
+        let writer = ShardWriter::new(128);
+        assert!(writer.into_shards().is_empty());
+    }
+}
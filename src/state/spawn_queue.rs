@@ -0,0 +1,124 @@
+//! Requests of creeps to spawn, queued per room
+//! Subsystems push requests, the spawns of the room pop them in order of priority
+//!
+use super::CreepMemory;
+use crate::creeps::spawn_info::BodyCollection;
+use crate::creeps::Role;
+
+#[derive(Debug, Clone)]
+pub struct SpawnRequest {
+    pub role: Role,
+    /// None to scale the default body of the role to the energy of the room
+    pub body: Option<BodyCollection>,
+    /// The higher the sooner
+    pub priority: i8,
+    /// Name of the subsystem requesting the creep
+    pub requester: &'static str,
+    /// Memory of the creep, its home and role are set by the spawn
+    pub memory: CreepMemory,
+    /// Game time the request is dropped at if it's not spawned yet
+    pub deadline: Option<u32>,
+}
+
+impl SpawnRequest {
+    pub fn new(role: Role, priority: i8, requester: &'static str) -> Self {
+        Self {
+            role,
+            body: None,
+            priority,
+            requester,
+            memory: CreepMemory::default(),
+            deadline: None,
+        }
+    }
+
+    pub fn with_deadline(mut self, deadline: u32) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    fn is_expired(&self, time: u32) -> bool {
+        self.deadline
+            .map(|deadline| deadline <= time)
+            .unwrap_or(false)
+    }
+}
+
+/// Requests in order of priority, requests of the same priority in order of arrival
+#[derive(Debug, Clone, Default)]
+pub struct SpawnQueue {
+    requests: Vec<SpawnRequest>,
+}
+
+impl SpawnQueue {
+    pub fn push(&mut self, request: SpawnRequest) {
+        let index = self
+            .requests
+            .iter()
+            .position(|r| r.priority < request.priority)
+            .unwrap_or(self.requests.len());
+        self.requests.insert(index, request);
+    }
+
+    /// The next request to spawn, dropping the expired ones
+    pub fn peek(&mut self, time: u32) -> Option<&SpawnRequest> {
+        self.requests.retain(|r| !r.is_expired(time));
+        self.requests.first()
+    }
+
+    pub fn pop(&mut self, time: u32) -> Option<SpawnRequest> {
+        self.peek(time)?;
+        Some(self.requests.remove(0))
+    }
+
+    /// Number of queued requests of the role
+    pub fn count(&self, role: Role) -> usize {
+        self.requests.iter().filter(|r| r.role == role).count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_are_popped_by_priority() {
+        let mut queue = SpawnQueue::default();
+        queue.push(SpawnRequest::new(Role::Worker, 1, "economy"));
+        queue.push(SpawnRequest::new(Role::Harvester, 3, "economy"));
+        queue.push(SpawnRequest::new(Role::Defender, 4, "defense"));
+        queue.push(SpawnRequest::new(Role::Upgrader, 1, "economy"));
+
+        let roles = (0..4)
+            .filter_map(|_| queue.pop(0))
+            .map(|r| r.role)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            roles,
+            vec![
+                Role::Defender,
+                Role::Harvester,
+                Role::Worker,
+                Role::Upgrader
+            ]
+        );
+        assert!(queue.pop(0).is_none());
+    }
+
+    #[test]
+    fn test_expired_requests_are_dropped() {
+        let mut queue = SpawnQueue::default();
+        queue.push(SpawnRequest::new(Role::Defender, 4, "defense").with_deadline(10));
+        queue.push(SpawnRequest::new(Role::Worker, 1, "economy"));
+        assert_eq!(queue.count(Role::Defender), 1);
+
+        assert_eq!(queue.peek(9).map(|r| r.role), Some(Role::Defender));
+        assert_eq!(queue.peek(10).map(|r| r.role), Some(Role::Worker));
+        assert_eq!(queue.count(Role::Defender), 0);
+        assert_eq!(queue.len(), 1);
+    }
+}
@@ -0,0 +1,251 @@
+//! Movement intents of the creeps of a room, resolved once all the creeps ran
+//! Creeps ask to move instead of moving, so the creeps standing in their way can be swapped
+//! with or shoved aside
+//!
+use super::path_cache::{direction_to, is_exit, neighbour};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone)]
+struct Intent {
+    from: (u8, u8),
+    /// None if the creep leaves the room
+    to: Option<(u8, u8)>,
+    direction: u8,
+    priority: i8,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Resolution {
+    /// Structure: creep -> direction
+    pub moves: BTreeMap<String, u8>,
+    /// Idle creeps moved out of the way
+    pub shoved: Vec<String>,
+    /// Creeps that can not move this tick, the creeps they wait on are blocked too
+    pub blocked: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TrafficManager {
+    /// Structure: tile -> creep standing on it
+    occupants: BTreeMap<(u8, u8), String>,
+    /// Creeps that are never shoved, e.g. creeps working in place
+    pinned: BTreeSet<String>,
+    /// Structure: creep -> intent
+    intents: BTreeMap<String, Intent>,
+}
+
+impl TrafficManager {
+    /// Register the creep standing on the tile, creeps without intents are idle
+    pub fn occupy(&mut self, creep: &str, pos: (u8, u8), pinned: bool) {
+        self.occupants.insert(pos, creep.to_owned());
+        if pinned {
+            self.pinned.insert(creep.to_owned());
+        }
+    }
+
+    /// The creep works in place this tick and is not shoved
+    pub fn pin(&mut self, creep: &str) {
+        self.pinned.insert(creep.to_owned());
+    }
+
+    /// The creep asks to move in the direction, the higher the priority the sooner it's resolved
+    pub fn request(&mut self, creep: &str, pos: (u8, u8), direction: u8, priority: i8) {
+        let intent = Intent {
+            from: pos,
+            to: neighbour(pos, direction),
+            direction,
+            priority,
+        };
+        self.intents.insert(creep.to_owned(), intent);
+    }
+
+    /// Decide the moves of the tick
+    /// Creeps moving into each other's tile swap, idle creeps are shoved to a free tile around
+    /// them or swapped with the creep moving into their tile
+    /// `walkable` tells if a tile is free of walls and obstacles
+    pub fn resolve<F>(&self, walkable: F) -> Resolution
+    where
+        F: Fn((u8, u8)) -> bool,
+    {
+        let targets = self
+            .intents
+            .values()
+            .filter_map(|intent| intent.to)
+            .collect::<BTreeSet<_>>();
+        let mut intents = self.intents.iter().collect::<Vec<_>>();
+        intents.sort_by_key(|&(creep, intent)| (Reverse(intent.priority), creep));
+
+        let mut result = Resolution::default();
+        // Structure: tile -> creep moving into it
+        let mut claimed = BTreeMap::new();
+        for (creep, intent) in intents {
+            let to = match intent.to {
+                Some(to) => to,
+                None => {
+                    result.moves.insert(creep.clone(), intent.direction);
+                    continue;
+                }
+            };
+            if claimed.contains_key(&to) {
+                continue;
+            }
+            let idle = self
+                .occupants
+                .get(&to)
+                .filter(|occupant| !self.intents.contains_key(*occupant))
+                .filter(|occupant| !result.moves.contains_key(*occupant));
+            if let Some(occupant) = idle {
+                if self.pinned.contains(occupant) {
+                    continue;
+                }
+                let free = |tile: &(u8, u8)| {
+                    !is_exit(*tile)
+                        && !self.occupants.contains_key(tile)
+                        && !claimed.contains_key(tile)
+                        && !targets.contains(tile)
+                        && walkable(*tile)
+                };
+                let shove = (1..=8)
+                    .filter_map(|direction| neighbour(to, direction))
+                    .find(|tile| free(tile));
+                let destination = match shove {
+                    Some(tile) => tile,
+                    None if !claimed.contains_key(&intent.from) => intent.from,
+                    None => continue,
+                };
+                if let Some(direction) = direction_to(to, destination) {
+                    claimed.insert(destination, occupant.clone());
+                    result.moves.insert(occupant.clone(), direction);
+                    result.shoved.push(occupant.clone());
+                }
+            }
+            claimed.insert(to, creep.clone());
+            result.moves.insert(creep.clone(), intent.direction);
+        }
+
+        self.cancel_blocked(&mut result);
+        result.blocked = self
+            .intents
+            .keys()
+            .filter(|creep| !result.moves.contains_key(*creep))
+            .cloned()
+            .collect();
+        result
+    }
+
+    /// Creeps moving into the tile of a creep that stays are blocked, so are the creeps following
+    /// them. Creeps swapping or rotating move together
+    fn cancel_blocked(&self, result: &mut Resolution) {
+        let waits_on = |creep: &str, moves: &BTreeMap<String, u8>| {
+            self.intents
+                .get(creep)
+                .and_then(|intent| intent.to)
+                .and_then(|to| self.occupants.get(&to))
+                .filter(|occupant| occupant.as_str() != creep)
+                .map(|occupant| (occupant.clone(), moves.contains_key(occupant)))
+        };
+
+        loop {
+            let blocked = result
+                .moves
+                .keys()
+                .filter(|creep| match waits_on(creep, &result.moves) {
+                    Some((_, moving)) => !moving,
+                    None => false,
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            if blocked.is_empty() {
+                break;
+            }
+            for creep in blocked {
+                result.moves.remove(&creep);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOP: u8 = 1;
+    const RIGHT: u8 = 3;
+    const BOTTOM: u8 = 5;
+    const LEFT: u8 = 7;
+
+    #[test]
+    fn test_swaps_and_shoves() {
+        let mut traffic = TrafficManager::default();
+        // a and b walk into each other
+        traffic.occupy("a", (10, 10), false);
+        traffic.occupy("b", (11, 10), false);
+        traffic.request("a", (10, 10), RIGHT, 0);
+        traffic.request("b", (11, 10), LEFT, 0);
+        // c walks into the idle upgrader, which is shoved aside
+        traffic.occupy("c", (20, 20), false);
+        traffic.occupy("upgrader", (20, 21), false);
+        traffic.request("c", (20, 20), BOTTOM, 0);
+
+        let result = traffic.resolve(|_| true);
+        assert_eq!(result.moves.get("a"), Some(&RIGHT));
+        assert_eq!(result.moves.get("b"), Some(&LEFT));
+        assert_eq!(result.moves.get("c"), Some(&BOTTOM));
+        assert!(result.moves.contains_key("upgrader"));
+        assert_eq!(result.shoved, vec!["upgrader".to_owned()]);
+        assert!(result.blocked.is_empty());
+
+        // Surrounded by walls the upgrader swaps with c
+        let result = traffic.resolve(|_| false);
+        assert_eq!(result.moves.get("upgrader"), Some(&TOP));
+    }
+
+    #[test]
+    fn test_blocked_chains_and_rotations() {
+        let mut traffic = TrafficManager::default();
+        // b follows the miner, a follows b
+        traffic.occupy("miner", (10, 10), true);
+        traffic.occupy("b", (10, 11), false);
+        traffic.occupy("a", (10, 12), false);
+        traffic.request("b", (10, 11), TOP, 0);
+        traffic.request("a", (10, 12), TOP, 0);
+
+        // Three creeps walking in a circle
+        traffic.occupy("x", (20, 20), false);
+        traffic.occupy("y", (21, 20), false);
+        traffic.occupy("z", (21, 21), false);
+        traffic.request("x", (20, 20), RIGHT, 0);
+        traffic.request("y", (21, 20), BOTTOM, 0);
+        traffic.request("z", (21, 21), 8, 0);
+
+        // The upgrader works in place
+        traffic.occupy("upgrader", (30, 30), false);
+        traffic.pin("upgrader");
+        traffic.occupy("c", (30, 31), false);
+        traffic.request("c", (30, 31), TOP, 0);
+
+        let result = traffic.resolve(|_| true);
+        assert_eq!(result.moves.get("x"), Some(&RIGHT));
+        assert_eq!(result.moves.get("y"), Some(&BOTTOM));
+        assert_eq!(result.moves.get("z"), Some(&8));
+        assert_eq!(result.moves.len(), 3);
+        assert_eq!(
+            result.blocked,
+            vec!["a".to_owned(), "b".to_owned(), "c".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_higher_priority_wins_the_tile() {
+        let mut traffic = TrafficManager::default();
+        traffic.occupy("a", (10, 10), false);
+        traffic.occupy("b", (12, 10), false);
+        traffic.request("a", (10, 10), RIGHT, 0);
+        traffic.request("b", (12, 10), LEFT, 1);
+
+        let result = traffic.resolve(|_| true);
+        assert_eq!(result.moves.get("b"), Some(&LEFT));
+        assert_eq!(result.blocked, vec!["a".to_owned()]);
+    }
+}
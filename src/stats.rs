@@ -1,8 +1,9 @@
 use super::bt::*;
 use super::creeps;
 use crate::state::GameState;
-use crate::{DEPLOYMENT_TIME, STATISTICS_SEGMENT, VERSION, COLLECT_STATS};
+use crate::{DEPLOYMENT_TIME, STATISTICS_SEGMENT, TRACES_SEGMENT, VERSION};
 use screeps::raw_memory;
+use std::collections::BTreeMap;
 
 #[derive(Serialize, Deserialize, Debug)]
 struct TickStats {
@@ -17,6 +18,9 @@ struct TickStats {
     gcl_progress: f32,
     gcl_progress_total: f32,
     creep_stats: creeps::CreepExecutionStats,
+    /// Structure: subsystem -> number of times it was deferred
+    #[serde(default)]
+    deferrals: BTreeMap<String, u32>,
 }
 
 pub fn save_stats(
@@ -26,7 +30,7 @@ pub fn save_stats(
     bucket: i32,
     state: &GameState,
 ) -> ExecutionResult {
-    if !COLLECT_STATS {
+    if !state.config.collect_stats {
         Err("Stat collection in disabled")?;
     }
     let mut stats: Vec<TickStats> = raw_memory::get_segment(STATISTICS_SEGMENT)
@@ -50,6 +54,7 @@ pub fn save_stats(
         gcl_progress,
         gcl_progress_total,
         creep_stats: state.creep_stats.clone(),
+        deferrals: state.scheduler.deferrals.clone(),
     };
 
     stats.push(tick_stats);
@@ -62,5 +67,49 @@ pub fn save_stats(
 
     raw_memory::set_segment(STATISTICS_SEGMENT, &data);
 
-    Ok(())
+    Ok(Status::Success)
+}
+
+#[derive(Serialize, Debug)]
+struct Traces<'a> {
+    time: u32,
+    traces: &'a BTreeMap<String, Vec<TraceEntry>>,
+}
+
+/// Should behaviour trees be sampled in this tick, see `Config::trace_interval`
+pub fn is_trace_tick(state: &GameState) -> bool {
+    let interval = state.config.trace_interval;
+    interval > 0 && screeps::game::time() % interval == 0
+}
+
+/// Overwrite the traces segment with the traces sampled this tick
+/// Read them from the console: `RawMemory.segments[3]`
+pub fn save_traces(state: &GameState) -> ExecutionResult {
+    if state.traces.is_empty() {
+        Err("No traces were sampled")?;
+    }
+
+    for (name, trace) in state.traces.iter() {
+        for entry in trace.iter() {
+            debug!(
+                "Trace {} [{}] {}: {} CPU: {:.4}",
+                name, entry.path, entry.node, entry.result, entry.cpu
+            );
+        }
+    }
+
+    let traces = Traces {
+        time: screeps::game::time(),
+        traces: &state.traces,
+    };
+    let data = serde_json::to_string(&traces)
+        .map_err(|e| format!("Failed to serialize traces {:?}", e))?;
+
+    if data.len() > 100 * 1024 {
+        Err("Traces do not fit in a segment")?;
+    }
+
+    raw_memory::set_segment(TRACES_SEGMENT, &data);
+
+    Ok(Status::Success)
 }
@@ -1,77 +1,162 @@
 use crate::prelude::*;
+use crate::stats::is_trace_tick;
 use arrayvec::ArrayVec;
-use creeps::roles::{next_role, spawn_config_by_role, Role};
-use creeps::{CREEP_ROLE, HOME_ROOM};
+use creeps::roles::{role_priority, spawn_config_by_role, BodyCollection, Role, SpawnConfig};
 use screeps::{
     constants::find,
     game,
-    objects::{SpawnOptions, StructureSpawn},
+    objects::{Room, SpawnOptions, StructureSpawn},
     prelude::*,
     Part, ReturnCode,
 };
 
+/// Roles the spawns request to keep the economy of the rooms running
+const ECONOMY_ROLES: &[Role] = &[
+    Role::Harvester,
+    Role::Miner,
+    Role::Gofer,
+    Role::Worker,
+    Role::Upgrader,
+    Role::Scout,
+];
+
+/// Economy requests not spawned in this many ticks are dropped, they are pushed again if needed
+const ECONOMY_REQUEST_TTL: u32 = 100;
+
 /// Return the BehaviourTree that runs the spawns
 pub fn run<'a>(state: &mut GameState) -> ExecutionResult {
-    Task::new(|state| {
-        const SPAWN_SKIP: u32 = 5;
-
+    Task::new(|state: &mut GameState| {
         let time = game::time();
-        if time % SPAWN_SKIP != 0 {
-            Err("Skip spawns this tick")?;
+        if time % state.config.spawn_skip != 0 {
+            Err(ExecutionError::WrongState)?;
+        }
+        // Sample the first room with spawns
+        let mut trace = is_trace_tick(state);
+        for room in game::rooms::values() {
+            if room.find(find::MY_SPAWNS).is_empty() {
+                continue;
+            }
+            if trace {
+                state.tracer = Some(Tracer::new(game::cpu::get_used));
+                trace = false;
+            }
+            let name = room.name();
+            run_room(&mut WrappedState::new(room, state))
+                .map(|_| ())
+                .unwrap_or_else(|e| debug!("Spawns of room {} stopped: {}", name, e));
+            if let Some(tracer) = state.tracer.take() {
+                state
+                    .traces
+                    .insert(format!("Spawns {}", name), tracer.into_entries());
+            }
         }
-        let rooms = game::rooms::values();
-        rooms
-            .into_iter()
-            .map(|room| room.find(find::MY_SPAWNS))
-            .filter(|spawns| spawns.len() > 0)
-            .for_each(move |spawns| {
-                let index = time as usize % spawns.len();
-                let spawn = &spawns[index as usize];
-                run_spawn(state, spawn).unwrap_or(())
-            });
-        Ok(())
+        Ok(Status::Success)
     })
     .with_required_bucket(500)
     .tick(state)
 }
 
-fn run_spawn<'a>(state: &'a mut GameState, spawn: &'a StructureSpawn) -> ExecutionResult {
-    debug!("Running spawn {}", spawn.name());
-
-    let room = &spawn.room();
-    let next_role = next_role(state, room);
+fn run_room(room: &mut WrappedState<Room, GameState>) -> ExecutionResult {
+    let tasks = [
+        Task::new(|room: &mut WrappedState<Room, GameState>| {
+            for role in ECONOMY_ROLES.iter() {
+                request_missing(
+                    room.state,
+                    &room.item,
+                    *role,
+                    "economy",
+                    ECONOMY_REQUEST_TTL,
+                );
+            }
+            Ok(Status::Success)
+        })
+        .with_name("Request economy roles"),
+        Task::new(|room: &mut WrappedState<Room, GameState>| {
+            let spawns = room.item.find(find::MY_SPAWNS);
+            run_spawns(room.state, &room.item, &spawns)
+        })
+        .with_name("Run spawns"),
+    ];
+    selector(room, tasks.iter())
+}
 
-    if next_role.is_none() {
-        debug!("Skipping spawn due to overpopulation");
-        return Ok(());
+/// Request the creeps of the role the room is missing by its quota
+/// The requests are dropped if they are not spawned in `ttl` ticks
+pub fn request_missing(
+    state: &mut GameState,
+    room: &Room,
+    role: Role,
+    requester: &'static str,
+    ttl: u32,
+) {
+    let quota = state.room_state(room).quota(role);
+    let missing = quota - state.count_creeps_and_requests(room, role);
+    if missing <= 0 {
+        return;
+    }
+    debug!("Room {} requests {} {} creeps", room.name(), missing, role);
+    let priority = role_priority(room, role);
+    let deadline = game::time() + ttl;
+    let queue = state.spawn_queue(WorldPosition::from(room));
+    for _ in 0..missing {
+        queue.push(SpawnRequest::new(role, priority, requester).with_deadline(deadline));
     }
+}
 
-    let next_role = next_role.unwrap();
+/// Spawn the requests of the room on every idle spawn
+fn run_spawns(state: &mut GameState, room: &Room, spawns: &[StructureSpawn]) -> ExecutionResult {
+    let time = game::time();
+    let room_pos = WorldPosition::from(room);
+    for spawn in spawns.iter().filter(|spawn| !spawn.is_spawning()) {
+        let request = match state.spawn_queue(room_pos).peek(time) {
+            Some(request) => request.clone(),
+            None => {
+                debug!("Nothing to spawn in room {}", room.name());
+                break;
+            }
+        };
 
-    match next_role {
-        Role::Defender | Role::Harvester | Role::Gofer => {}
-        _ => {
+        if !request.role.is_critical() {
             // If the room has enough harvesters and gofers
-            let total = room.energy_capacity_available() as f32;
+            let total = state.room_state(room).energy_capacity as f32;
             let actual = room.energy_available() as f32;
 
             if (actual / total) < 0.75 {
-                Err("Wait for more energy before spawning next creep")?;
+                debug!("Wait for more energy before spawning next creep");
+                Err(ExecutionError::Empty)?;
             }
         }
-    }
 
-    spawn_creep(state, &spawn, next_role)?;
+        spawn_creep(state, spawn, &request)?;
+        debug!(
+            "Spawning a {} requested by {}",
+            request.role, request.requester
+        );
 
-    Ok(())
+        state.spawn_queue(room_pos).pop(time);
+        state.invalidate_creep_count(room);
+    }
+    Ok(Status::Success)
 }
 
-fn spawn_creep(state: &mut GameState, spawn: &StructureSpawn, role: Role) -> ExecutionResult {
+fn spawn_creep(
+    state: &mut GameState,
+    spawn: &StructureSpawn,
+    request: &SpawnRequest,
+) -> ExecutionResult {
     trace!("Spawning creep");
 
     let room = spawn.room();
-
-    let spawn_config = spawn_config_by_role(&room, role);
+    let role = request.role;
+
+    let spawn_config = match request.body {
+        Some(ref body) => SpawnConfig {
+            basic_body: body.clone(),
+            body_extension: BodyCollection::new(),
+            body_max: None,
+        },
+        None => spawn_config_by_role(&room, role),
+    };
 
     let mut body = spawn_config
         .basic_body
@@ -98,7 +183,7 @@ fn spawn_creep(state: &mut GameState, spawn: &StructureSpawn, role: Role) -> Exe
                 break;
             } else {
                 warn!("Can not spawn, error: {:?}", result);
-                return Err("Can not spawn".into());
+                return Err(result.into());
             }
         }
     }
@@ -115,8 +200,9 @@ fn spawn_creep(state: &mut GameState, spawn: &StructureSpawn, role: Role) -> Exe
             }
             ReturnCode::Ok => {
                 let memory = state.creep_memory_entry(CreepName(&name));
-                memory.insert(HOME_ROOM.into(), spawn.room().name().into());
-                memory.insert(CREEP_ROLE.into(), (role as i64).into());
+                *memory = request.memory.clone();
+                memory.home = Some(spawn.room().name());
+                memory.role = Some(role);
                 info!(
                     "Spawn {} is spawning creep: {}, result: {}",
                     spawn.name(),
@@ -132,8 +218,8 @@ fn spawn_creep(state: &mut GameState, spawn: &StructureSpawn, role: Role) -> Exe
     };
 
     if res != ReturnCode::Ok {
-        Err(format!("Failed to spawn: {:?}", res))?;
+        Err(res)?;
     }
-    Ok(())
+    Ok(Status::Success)
 }
 
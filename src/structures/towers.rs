@@ -1,5 +1,6 @@
 use crate::creeps::find_repair_target;
 use crate::prelude::*;
+use crate::stats::is_trace_tick;
 use screeps::{
     constants::find,
     game,
@@ -8,6 +9,8 @@ use screeps::{
 };
 
 pub fn run<'a>(state: &mut GameState) -> ExecutionResult {
+    // Sample the first tower
+    let mut trace = is_trace_tick(state);
     game::structures::values()
         .into_iter()
         .filter_map(|s| match s {
@@ -15,15 +18,32 @@ pub fn run<'a>(state: &mut GameState) -> ExecutionResult {
             _ => None,
         })
         .for_each(move |tower| {
-            let mut state = WrappedState::new(tower, state);
-            run_tower(&mut state)
-                .map_err(move |e| {
-                    debug!("Tower in room {:?} is idle, {:?}", state.item.room().name(), e);
-                    e
-                })
-                .unwrap_or(())
+            if trace {
+                state.tracer = Some(Tracer::new(game::cpu::get_used));
+                trace = false;
+            }
+            let id = tower.id();
+            {
+                let mut state = WrappedState::new(tower, state);
+                run_tower(&mut state)
+                    .map_err(move |e| {
+                        debug!(
+                            "Tower in room {:?} is idle, {:?}",
+                            state.item.room().name(),
+                            e
+                        );
+                        e
+                    })
+                    .map(|_| ())
+                    .unwrap_or(())
+            }
+            if let Some(tracer) = state.tracer.take() {
+                state
+                    .traces
+                    .insert(format!("Tower {}", id), tracer.into_entries());
+            }
         });
-    Ok(())
+    Ok(Status::Success)
 }
 
 fn run_tower<'a>(tower: &mut WrappedState<StructureTower, GameState>) -> ExecutionResult {
@@ -42,14 +62,14 @@ fn attempt_attack<'a>(tower: &'a StructureTower) -> ExecutionResult {
     if let Some(enemy) = enemy {
         let res = tower.attack(&enemy);
         match res {
-            ReturnCode::Ok | ReturnCode::RclNotEnough => Ok(()),
+            ReturnCode::Ok | ReturnCode::RclNotEnough => Ok(Status::Success),
             _ => {
                 error!("Failed to attack enemy {:?}", res);
-                Err("Failed to attack enemy")?
+                Err(res)?
             }
         }
     } else {
-        Err("no target")?
+        Err(ExecutionError::NoTarget)?
     }
 }
 
@@ -61,12 +81,11 @@ pub fn attempt_repair<'a>(tower: &'a StructureTower) -> ExecutionResult {
     trace!("Repairing");
 
     if tower.energy() < tower.energy_capacity() * 3 / 4 {
-        return Err("loading".into());
+        return Err(ExecutionError::WrongState);
     }
     let target = find_repair_target(&tower.room()).ok_or_else(|| {
-        let error = format!("Could not find a repair target");
-        debug!("{}", error);
-        error
+        debug!("Could not find a repair target");
+        ExecutionError::NoTarget
     })?;
     trace!("Got repair target {:?}", target.id());
     repair(tower, &target)
@@ -75,10 +94,9 @@ pub fn attempt_repair<'a>(tower: &'a StructureTower) -> ExecutionResult {
 fn repair<'a>(tower: &'a StructureTower, target: &'a Structure) -> ExecutionResult {
     let res = tower.repair(target);
     if res == ReturnCode::Ok {
-        Ok(())
+        Ok(Status::Success)
     } else {
-        let error = format!("Unexpected ReturnCode {:?}", res);
-        Err(error)?
+        Err(res)?
     }
 }
 